@@ -0,0 +1,79 @@
+//! Compare solve times of the variable encodings on the circuits from the
+//! tests. Run with `cargo bench --bench encoding`.
+
+use dpqa_rs::circuit::Circuit;
+use dpqa_rs::dpqa::{Encoding, DPQA};
+use dpqa_rs::gates::TwoQubitGate;
+use dpqa_rs::gates::TwoQubitGateType::{CX, CZ};
+use std::time::{Duration, Instant};
+
+const REPETITIONS: u32 = 3;
+
+fn circuit(gates: &[(usize, usize)]) -> Circuit {
+    let mut circuit = Circuit::new();
+    for &(q0, q1) in gates {
+        circuit.append(TwoQubitGate::new(CZ, q0, q1));
+    }
+    circuit.recalculate_stages();
+    circuit
+}
+
+fn benchmarks() -> Vec<(&'static str, DPQA, Circuit)> {
+    let mut spaced = DPQA::new(3, 3);
+    spaced.set_interaction_radius(2);
+    let mut mixed = circuit(&[(0, 2), (1, 3)]);
+    mixed.append(TwoQubitGate::new(CX, 4, 5));
+    mixed.append(TwoQubitGate::new(CX, 6, 7));
+    mixed.recalculate_stages();
+
+    vec![
+        ("two_gates", DPQA::new(2, 1), circuit(&[(0, 1), (1, 2)])),
+        (
+            "six_gates",
+            DPQA::new_aod(3, 2, 1, 2),
+            circuit(&[(0, 2), (1, 3), (0, 4), (1, 5), (0, 6), (1, 7)]),
+        ),
+        (
+            "fig_2",
+            DPQA::new(2, 4),
+            circuit(&[
+                (2, 4),
+                (3, 5),
+                (0, 1),
+                (2, 3),
+                (4, 5),
+                (0, 2),
+                (1, 3),
+                (0, 4),
+                (1, 5),
+            ]),
+        ),
+        ("gate_types", DPQA::new(2, 3), mixed),
+        ("radius_2", spaced, circuit(&[(0, 1), (1, 2), (2, 3)])),
+    ]
+}
+
+fn main() {
+    let encodings = [Encoding::Int, Encoding::BitVec, Encoding::OneHot];
+    print!("{:<12}", "circuit");
+    for encoding in encodings {
+        print!("{:>12}", format!("{:?}", encoding));
+    }
+    println!();
+
+    for (name, mut dpqa, circuit) in benchmarks() {
+        print!("{:<12}", name);
+        for encoding in encodings {
+            dpqa.set_encoding(encoding);
+            let mut total = Duration::ZERO;
+            for _ in 0..REPETITIONS {
+                let start = Instant::now();
+                dpqa.solve(&circuit)
+                    .unwrap_or_else(|e| panic!("{} failed with {:?}: {}", name, encoding, e));
+                total += start.elapsed();
+            }
+            print!("{:>10.1}ms", (total / REPETITIONS).as_secs_f64() * 1e3);
+        }
+        println!();
+    }
+}
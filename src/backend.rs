@@ -0,0 +1,627 @@
+use crate::error::DPQAError;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::time::Instant;
+use z3::{
+    ast::{self, Ast},
+    Context, DeclKind, Model, Optimize, Params, SatResult, Solver,
+};
+
+/// Destination of the constraints and objectives built by `DPQAVars`. The
+/// constraints are Z3 expressions, so this only abstracts over the ways of
+/// using them with Z3: solving with `Optimize` or `LexOptimize`, counting
+/// them (`CountingSink`), or exporting them to other solvers as an SMT-LIB2
+/// script (`SmtLib2Writer`). Solvers without Z3 go through the script.
+pub trait ConstraintSink<'ctx> {
+    fn get_context(&self) -> &'ctx Context;
+    fn assert(&self, ast: &ast::Bool<'ctx>);
+    fn assert_soft(&self, ast: &ast::Bool<'ctx>, weight: u64);
+    /// Add an objective. Objectives are optimized in the order they are
+    /// added, after the soft constraints added before them.
+    fn minimize(&self, ast: &ast::Int<'ctx>);
+}
+
+/// Solve directly with Z3
+impl<'ctx> ConstraintSink<'ctx> for Optimize<'ctx> {
+    fn get_context(&self) -> &'ctx Context {
+        Optimize::get_context(self)
+    }
+
+    fn assert(&self, ast: &ast::Bool<'ctx>) {
+        Optimize::assert(self, ast);
+    }
+
+    fn assert_soft(&self, ast: &ast::Bool<'ctx>, weight: u64) {
+        Optimize::assert_soft(self, ast, weight, None);
+    }
+
+    fn minimize(&self, ast: &ast::Int<'ctx>) {
+        Optimize::minimize(self, ast);
+    }
+}
+
+/// Objective of a `LexOptimize`
+enum Objective<'ctx> {
+    /// Soft constraints with their weights, minimizing the total weight of
+    /// those violated
+    Soft(Vec<(ast::Bool<'ctx>, u64)>),
+    Minimize(ast::Int<'ctx>),
+}
+
+impl<'ctx> Objective<'ctx> {
+    fn cost(&self, ctx: &'ctx Context) -> ast::Int<'ctx> {
+        match self {
+            Objective::Soft(soft) => {
+                let zero = ast::Int::from_u64(ctx, 0);
+                let violated: Vec<ast::Int<'ctx>> = soft
+                    .iter()
+                    .map(|(b, w)| b.ite(&zero, &ast::Int::from_u64(ctx, *w)))
+                    .collect();
+                let refs: Vec<&ast::Int<'ctx>> = violated.iter().collect();
+                ast::Int::add(ctx, &refs)
+            }
+            Objective::Minimize(ast) => ast.clone(),
+        }
+    }
+}
+
+/// Solve with a Z3 `Solver`, optimizing the objectives one after the other
+/// by asking for strictly better models until there are none. Unlike
+/// `Optimize`, the solver takes its random seed as a parameter, so solves
+/// with different seeds can run at the same time.
+pub struct LexOptimize<'ctx> {
+    solver: Solver<'ctx>,
+    objectives: RefCell<Vec<Objective<'ctx>>>,
+    seed: Option<u32>,
+    deadline: Option<Instant>,
+    stop: Option<Box<dyn Fn() -> bool + 'ctx>>,
+    model: RefCell<Option<Model<'ctx>>>,
+    reason_unknown: RefCell<Option<String>>,
+}
+
+impl<'ctx> LexOptimize<'ctx> {
+    pub fn new(ctx: &'ctx Context) -> LexOptimize<'ctx> {
+        LexOptimize {
+            // Every variable has a bounded domain, so the constraints can be
+            // bit-blasted to SAT, which is much faster when checking again
+            // with tighter bounds
+            solver: Solver::new_for_logic(ctx, "QF_FD").unwrap_or_else(|| Solver::new(ctx)),
+            objectives: RefCell::new(vec![]),
+            seed: None,
+            deadline: None,
+            stop: None,
+            model: RefCell::new(None),
+            reason_unknown: RefCell::new(None),
+        }
+    }
+
+    pub fn set_random_seed(&mut self, seed: u32) {
+        self.seed = Some(seed);
+    }
+
+    /// Stop checking at the given time, over all the checks made while
+    /// optimizing
+    pub fn set_deadline(&mut self, deadline: Instant) {
+        self.deadline = Some(deadline);
+    }
+
+    /// Stop optimizing before the next check once `stop` returns true, e.g.
+    /// when the solve is cancelled. Interrupting the context only stops the
+    /// check in progress.
+    pub fn set_stop(&mut self, stop: impl Fn() -> bool + 'ctx) {
+        self.stop = Some(Box::new(stop));
+    }
+
+    /// Check whether a model satisfies the assumptions, which should be
+    /// Boolean constants, without optimizing. The solver keeps the phases of
+    /// that model, which can speed up the next check.
+    pub fn check_assumptions(&self, assumptions: &[ast::Bool<'ctx>]) -> SatResult {
+        *self.model.borrow_mut() = None;
+        self.check_once(assumptions)
+    }
+
+    /// Find a model satisfying the assumptions, which should be Boolean
+    /// constants, then optimize the objectives in turn. `found` is called
+    /// with the value of every objective for the first model and each
+    /// better one. If a check times out or is interrupted, returns
+    /// `Unknown` and keeps the best model found so far.
+    pub fn check(&self, assumptions: &[ast::Bool<'ctx>], found: &impl Fn(&[i64])) -> SatResult {
+        *self.model.borrow_mut() = None;
+        let status = self.check_once(assumptions);
+        if status != SatResult::Sat {
+            return status;
+        }
+
+        let ctx = self.solver.get_context();
+        let costs: Vec<ast::Int<'ctx>> = self
+            .objectives
+            .borrow()
+            .iter()
+            .map(|o| o.cost(ctx))
+            .collect();
+        let values = self.keep_model(&costs);
+        found(&values);
+        // Fixing the optimal values must not outlive this check
+        self.solver.push();
+        let status = self.optimize(assumptions, &costs, values, found);
+        self.solver.pop(1);
+        status
+    }
+
+    fn optimize(
+        &self,
+        assumptions: &[ast::Bool<'ctx>],
+        costs: &[ast::Int<'ctx>],
+        mut values: Vec<i64>,
+        found: &impl Fn(&[i64]),
+    ) -> SatResult {
+        for (ii, cost) in costs.iter().enumerate() {
+            loop {
+                self.solver.push();
+                self.solver
+                    .assert(&cost.lt(&ast::Int::from_i64(self.solver.get_context(), values[ii])));
+                let status = self.check_once(assumptions);
+                if status == SatResult::Sat {
+                    values = self.keep_model(costs);
+                    found(&values);
+                }
+                self.solver.pop(1);
+                match status {
+                    SatResult::Sat => {}
+                    SatResult::Unsat => break,
+                    SatResult::Unknown => return status,
+                }
+            }
+            let best = ast::Int::from_i64(self.solver.get_context(), values[ii]);
+            self.solver.assert(&cost._eq(&best));
+        }
+        SatResult::Sat
+    }
+
+    /// Keep the model of the last check, and get the objective values
+    fn keep_model(&self, costs: &[ast::Int<'ctx>]) -> Vec<i64> {
+        let model = self.solver.get_model();
+        let values = costs
+            .iter()
+            .map(|c| {
+                model
+                    .as_ref()
+                    .and_then(|m| m.eval(c, true))
+                    .and_then(|v| v.as_i64())
+                    .unwrap_or_default()
+            })
+            .collect();
+        *self.model.borrow_mut() = model;
+        values
+    }
+
+    fn check_once(&self, assumptions: &[ast::Bool<'ctx>]) -> SatResult {
+        if self.stop.as_ref().is_some_and(|stop| stop()) {
+            *self.reason_unknown.borrow_mut() = Some(String::from("canceled"));
+            return SatResult::Unknown;
+        }
+        let mut params = Params::new(self.solver.get_context());
+        if let Some(seed) = self.seed {
+            params.set_u32("random_seed", seed);
+        }
+        if let Some(deadline) = self.deadline {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                *self.reason_unknown.borrow_mut() = Some(String::from("timeout"));
+                return SatResult::Unknown;
+            }
+            params.set_u32(
+                "timeout",
+                remaining.as_millis().clamp(1, u32::MAX as u128) as u32,
+            );
+        }
+        self.solver.set_params(&params);
+
+        let status = self.solver.check_assumptions(assumptions);
+        if status == SatResult::Unknown {
+            *self.reason_unknown.borrow_mut() = self.solver.get_reason_unknown();
+        }
+        status
+    }
+
+    /// Take the best model found by the last check
+    pub fn take_model(&self) -> Option<Model<'ctx>> {
+        self.model.borrow_mut().take()
+    }
+
+    pub fn get_reason_unknown(&self) -> Option<String> {
+        self.reason_unknown.borrow().clone()
+    }
+}
+
+impl<'ctx> ConstraintSink<'ctx> for LexOptimize<'ctx> {
+    fn get_context(&self) -> &'ctx Context {
+        self.solver.get_context()
+    }
+
+    fn assert(&self, ast: &ast::Bool<'ctx>) {
+        self.solver.assert(ast);
+    }
+
+    fn assert_soft(&self, ast: &ast::Bool<'ctx>, weight: u64) {
+        let mut objectives = self.objectives.borrow_mut();
+        match objectives.last_mut() {
+            Some(Objective::Soft(soft)) => soft.push((ast.clone(), weight)),
+            _ => objectives.push(Objective::Soft(vec![(ast.clone(), weight)])),
+        }
+    }
+
+    fn minimize(&self, ast: &ast::Int<'ctx>) {
+        self.objectives
+            .borrow_mut()
+            .push(Objective::Minimize(ast.clone()));
+    }
+}
+
+/// Passes constraints and objectives on to another sink, counting the
+/// assertions (including soft constraints)
+pub struct CountingSink<'a, S> {
+    inner: &'a S,
+    n_assertions: Cell<usize>,
+}
+
+impl<'a, S> CountingSink<'a, S> {
+    pub fn new(inner: &'a S) -> CountingSink<'a, S> {
+        CountingSink {
+            inner,
+            n_assertions: Cell::new(0),
+        }
+    }
+
+    /// Get the number of assertions passed on so far
+    pub fn n_assertions(&self) -> usize {
+        self.n_assertions.get()
+    }
+}
+
+impl<'ctx, S: ConstraintSink<'ctx>> ConstraintSink<'ctx> for CountingSink<'_, S> {
+    fn get_context(&self) -> &'ctx Context {
+        self.inner.get_context()
+    }
+
+    fn assert(&self, ast: &ast::Bool<'ctx>) {
+        self.n_assertions.set(self.n_assertions.get() + 1);
+        self.inner.assert(ast);
+    }
+
+    fn assert_soft(&self, ast: &ast::Bool<'ctx>, weight: u64) {
+        self.n_assertions.set(self.n_assertions.get() + 1);
+        self.inner.assert_soft(ast, weight);
+    }
+
+    fn minimize(&self, ast: &ast::Int<'ctx>) {
+        self.inner.minimize(ast);
+    }
+}
+
+/// Collects the constraints and objectives as an SMT-LIB2 script, which
+/// declares every constant, asserts the constraints, and ends with
+/// `(check-sat)` and `(get-model)`. Soft constraints use the `assert-soft`
+/// extension, and objectives `minimize`, as supported by Z3 and OptiMathSAT.
+pub struct SmtLib2Writer<'ctx> {
+    ctx: &'ctx Context,
+    /// Declared constants, in the order they were found
+    declarations: RefCell<Vec<String>>,
+    names: RefCell<HashSet<String>>,
+    commands: RefCell<Vec<String>>,
+}
+
+impl<'ctx> SmtLib2Writer<'ctx> {
+    pub fn new(ctx: &'ctx Context) -> SmtLib2Writer<'ctx> {
+        SmtLib2Writer {
+            ctx,
+            declarations: RefCell::new(vec![]),
+            names: RefCell::new(HashSet::new()),
+            commands: RefCell::new(vec![]),
+        }
+    }
+
+    /// Declare the uninterpreted constants in `ast` that were not seen yet
+    fn declare(&self, ast: &ast::Dynamic<'ctx>, visited: &mut HashSet<ast::Dynamic<'ctx>>) {
+        if !visited.insert(ast.clone()) {
+            return;
+        }
+        if ast.is_const() && ast.decl().kind() == DeclKind::UNINTERPRETED {
+            let name = ast.decl().name();
+            if self.names.borrow_mut().insert(name.clone()) {
+                self.declarations.borrow_mut().push(format!(
+                    "(declare-const {} {})",
+                    name,
+                    ast.get_sort()
+                ));
+            }
+            return;
+        }
+        if ast.is_app() {
+            for child in ast.children() {
+                self.declare(&child, visited);
+            }
+        }
+    }
+
+    fn push(&self, ast: &impl Ast<'ctx>, command: String) {
+        self.declare(&ast::Dynamic::from_ast(ast), &mut HashSet::new());
+        self.commands.borrow_mut().push(command);
+    }
+}
+
+impl<'ctx> ConstraintSink<'ctx> for SmtLib2Writer<'ctx> {
+    fn get_context(&self) -> &'ctx Context {
+        self.ctx
+    }
+
+    fn assert(&self, ast: &ast::Bool<'ctx>) {
+        self.push(ast, format!("(assert {})", ast));
+    }
+
+    fn assert_soft(&self, ast: &ast::Bool<'ctx>, weight: u64) {
+        self.push(ast, format!("(assert-soft {} :weight {})", ast, weight));
+    }
+
+    fn minimize(&self, ast: &ast::Int<'ctx>) {
+        self.push(ast, format!("(minimize {})", ast));
+    }
+}
+
+impl fmt::Display for SmtLib2Writer<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "(set-option :produce-models true)")?;
+        for d in self.declarations.borrow().iter() {
+            writeln!(f, "{}", d)?;
+        }
+        for c in self.commands.borrow().iter() {
+            writeln!(f, "{}", c)?;
+        }
+        writeln!(f, "(check-sat)")?;
+        writeln!(f, "(get-model)")
+    }
+}
+
+/// Value of a constant in a model
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum ModelValue {
+    Int(i64),
+    Bool(bool),
+}
+
+/// S-expression in solver output
+enum SExpr {
+    Atom(String),
+    List(Vec<SExpr>),
+}
+
+fn parse_sexprs(text: &str) -> Result<Vec<SExpr>, DPQAError> {
+    let mut stack: Vec<Vec<SExpr>> = vec![vec![]];
+    let mut chars = text.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '(' => stack.push(vec![]),
+            ')' => {
+                let list = stack.pop().filter(|_| !stack.is_empty()).ok_or_else(|| {
+                    DPQAError::ModelExtraction(String::from("unbalanced parentheses"))
+                })?;
+                stack.last_mut().unwrap().push(SExpr::List(list));
+            }
+            ';' => {
+                // Comment until the end of the line
+                while chars.next_if(|&c| c != '\n').is_some() {}
+            }
+            '|' => {
+                let mut atom = String::new();
+                while let Some(c) = chars.next_if(|&c| c != '|') {
+                    atom.push(c);
+                }
+                chars.next();
+                stack.last_mut().unwrap().push(SExpr::Atom(atom));
+            }
+            c if c.is_whitespace() => {}
+            c => {
+                let mut atom = String::from(c);
+                while let Some(c) = chars.next_if(|&c| !c.is_whitespace() && c != '(' && c != ')') {
+                    atom.push(c);
+                }
+                stack.last_mut().unwrap().push(SExpr::Atom(atom));
+            }
+        }
+    }
+    match stack.pop() {
+        Some(top) if stack.is_empty() => Ok(top),
+        _ => Err(DPQAError::ModelExtraction(String::from(
+            "unbalanced parentheses",
+        ))),
+    }
+}
+
+fn parse_value(expr: &SExpr) -> Option<ModelValue> {
+    match expr {
+        SExpr::Atom(a) if a == "true" => Some(ModelValue::Bool(true)),
+        SExpr::Atom(a) if a == "false" => Some(ModelValue::Bool(false)),
+        SExpr::Atom(a) => {
+            // Bit-vectors are unsigned
+            let n = if let Some(bits) = a.strip_prefix("#b") {
+                i64::from_str_radix(bits, 2).ok()?
+            } else if let Some(hex) = a.strip_prefix("#x") {
+                i64::from_str_radix(hex, 16).ok()?
+            } else {
+                a.parse().ok()?
+            };
+            Some(ModelValue::Int(n))
+        }
+        SExpr::List(l) => match l.as_slice() {
+            [SExpr::Atom(minus), value] if minus == "-" => match parse_value(value)? {
+                ModelValue::Int(n) => Some(ModelValue::Int(-n)),
+                ModelValue::Bool(_) => None,
+            },
+            _ => None,
+        },
+    }
+}
+
+/// Collect `define-fun` entries, at any depth
+fn collect_values(exprs: &[SExpr], values: &mut HashMap<String, ModelValue>) {
+    for expr in exprs {
+        if let SExpr::List(l) = expr {
+            match l.as_slice() {
+                [SExpr::Atom(cmd), SExpr::Atom(name), SExpr::List(args), _, value]
+                    if cmd == "define-fun" && args.is_empty() =>
+                {
+                    if let Some(v) = parse_value(value) {
+                        values.insert(name.clone(), v);
+                    }
+                }
+                _ => collect_values(l, values),
+            }
+        }
+    }
+}
+
+/// Read the output of a solver run on an SMT-LIB2 script: the result of
+/// `(check-sat)` followed by the model, as a list of `define-fun`s.
+pub fn parse_model(text: &str) -> Result<HashMap<String, ModelValue>, DPQAError> {
+    let exprs = parse_sexprs(text)?;
+    match exprs.first() {
+        Some(SExpr::Atom(a)) if a == "unsat" => return Err(DPQAError::Unsatisfiable),
+        Some(SExpr::Atom(a)) if a == "unknown" => {
+            return Err(DPQAError::SolverUnknown(String::from(
+                "external solver returned unknown",
+            )))
+        }
+        _ => {}
+    }
+    let mut values = HashMap::new();
+    collect_values(&exprs, &mut values);
+    if values.is_empty() {
+        return Err(DPQAError::ModelExtraction(String::from(
+            "no model available",
+        )));
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        parse_model, ConstraintSink, CountingSink, LexOptimize, ModelValue, SmtLib2Writer,
+    };
+    use crate::error::DPQAError;
+    use std::cell::RefCell;
+    use z3::{ast, Config, Context, SatResult};
+
+    #[test]
+    fn lex_optimize() {
+        let ctx = Context::new(&Config::new());
+        let mut solver = LexOptimize::new(&ctx);
+        solver.set_random_seed(3);
+        let x = ast::Int::new_const(&ctx, "x");
+        let y = ast::Int::new_const(&ctx, "y");
+        let int = |v| ast::Int::from_u64(&ctx, v);
+        for v in [&x, &y] {
+            solver.assert(&v.ge(&int(0)));
+            solver.assert(&v.le(&int(10)));
+        }
+        solver.assert(&ast::Int::add(&ctx, &[&x, &y]).ge(&int(8)));
+        // The soft constraints come first, then the objectives in order
+        solver.assert_soft(&x.gt(&int(2)), 1);
+        solver.assert_soft(&x.lt(&int(2)), 2);
+        solver.minimize(&y);
+        solver.minimize(&x);
+
+        let found = RefCell::new(vec![]);
+        let record = |values: &[i64]| found.borrow_mut().push(values.to_vec());
+        assert_eq!(solver.check(&[], &record), SatResult::Sat);
+        // Each model is better than the previous one
+        let found = found.into_inner();
+        assert!(found.windows(2).all(|w| w[1] < w[0]));
+        assert_eq!(found.last().unwrap(), &[1, 7, 1]);
+        let model = solver.take_model().unwrap();
+        let value = |v: &ast::Int| model.eval(v, true).unwrap().as_u64().unwrap();
+        assert_eq!((value(&x), value(&y)), (1, 7));
+        // Bounds from the optimization do not carry over to the next check
+        let (high, too_high) = (
+            ast::Bool::new_const(&ctx, "high"),
+            ast::Bool::new_const(&ctx, "too_high"),
+        );
+        solver.assert(&high.implies(&y.gt(&int(9))));
+        solver.assert(&too_high.implies(&y.gt(&int(10))));
+        assert_eq!(
+            solver.check(std::slice::from_ref(&high), &|_| {}),
+            SatResult::Sat
+        );
+        assert!(solver.take_model().is_some());
+        assert_eq!(
+            solver.check(std::slice::from_ref(&too_high), &|_| {}),
+            SatResult::Unsat
+        );
+        assert_eq!(solver.check_assumptions(&[high]), SatResult::Sat);
+        assert_eq!(solver.check_assumptions(&[too_high]), SatResult::Unsat);
+    }
+
+    #[test]
+    fn count() {
+        let ctx = Context::new(&Config::new());
+        let writer = SmtLib2Writer::new(&ctx);
+        let counted = CountingSink::new(&writer);
+        let x = ast::Int::new_const(&ctx, "x");
+        counted.assert(&x.gt(&ast::Int::from_u64(&ctx, 2)));
+        counted.assert_soft(&x.lt(&ast::Int::from_u64(&ctx, 4)), 1);
+        counted.minimize(&x);
+        assert_eq!(counted.n_assertions(), 2);
+        assert!(writer.to_string().contains("(minimize x)"));
+    }
+
+    #[test]
+    fn write() {
+        let ctx = Context::new(&Config::new());
+        let writer = SmtLib2Writer::new(&ctx);
+        let x = ast::Int::new_const(&ctx, "x");
+        let b = ast::Bool::new_const(&ctx, "b");
+        writer.assert(&x.gt(&ast::Int::from_u64(&ctx, 2)));
+        writer.assert_soft(&b, 2);
+        writer.minimize(&x);
+        let text = writer.to_string();
+        assert_eq!(
+            text,
+            "\
+(set-option :produce-models true)
+(declare-const x Int)
+(declare-const b Bool)
+(assert (> x 2))
+(assert-soft b :weight 2)
+(minimize x)
+(check-sat)
+(get-model)
+"
+        );
+    }
+
+    #[test]
+    fn read() {
+        let output = "\
+sat
+(
+  (define-fun x_q0_t0 () Int
+    1)
+  (define-fun |aod_q0_t0| () Bool
+    true)
+  ; comment
+  (define-fun t_0 () Int (- 2))
+  (define-fun y_q0_t0 () (_ BitVec 3) #b101)
+  (define-fun c_q0_t0 () (_ BitVec 8) #x1f)
+)";
+        let values = parse_model(output).unwrap();
+        assert_eq!(values["x_q0_t0"], ModelValue::Int(1));
+        assert_eq!(values["aod_q0_t0"], ModelValue::Bool(true));
+        assert_eq!(values["t_0"], ModelValue::Int(-2));
+        assert_eq!(values["y_q0_t0"], ModelValue::Int(5));
+        assert_eq!(values["c_q0_t0"], ModelValue::Int(31));
+
+        assert_eq!(parse_model("unsat"), Err(DPQAError::Unsatisfiable));
+        assert!(parse_model("sat\n((define-fun x () Int 1)").is_err());
+    }
+}
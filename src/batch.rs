@@ -0,0 +1,269 @@
+use crate::{circuit::Circuit, dpqa::DPQA, error::DPQAError, result::DPQAResult};
+use std::fmt::Write as _;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Compilation of every circuit with every configuration, e.g. to find the
+/// smallest grid that fits each circuit. The jobs run on a pool of threads.
+/// ```
+/// use dpqa_rs::batch::Batch;
+/// use dpqa_rs::circuit::Circuit;
+/// use dpqa_rs::compiler::Compiler;
+/// use dpqa_rs::dpqa::DPQA;
+/// use dpqa_rs::gates::{TwoQubitGate, TwoQubitGateType::CZ};
+/// use dpqa_rs::greedy::GreedyCompiler;
+///
+/// let mut circuit = Circuit::new();
+/// circuit.append(TwoQubitGate::new(CZ, 0, 1));
+/// circuit.append(TwoQubitGate::new(CZ, 1, 2));
+/// let circuits = [circuit];
+///
+/// let configs: Vec<DPQA> = (1..=3).map(|n| DPQA::new(n, n)).collect();
+/// let batch = Batch::new(&circuits, &configs);
+/// let results = batch.run_with(|dpqa, circuit| GreedyCompiler::new(dpqa).compile(circuit));
+/// print!("{}", results.to_csv());
+/// let smallest = results.smallest_viable(0).unwrap();
+/// assert_eq!(configs[smallest.config].get_rows(), 2);
+/// ```
+pub struct Batch<'a> {
+    circuits: &'a [Circuit],
+    configs: &'a [DPQA],
+    n_threads: usize,
+}
+
+/// Outcome of compiling one circuit with one configuration
+#[derive(Debug)]
+pub struct BatchEntry {
+    /// Index of the circuit
+    pub circuit: usize,
+    /// Index of the configuration
+    pub config: usize,
+    pub result: DPQAResult,
+    /// Wall-clock time taken by the compilation
+    pub solve_time: Duration,
+}
+
+impl BatchEntry {
+    /// Get the number of stages, if the compilation succeeded
+    pub fn get_n_stages(&self) -> Option<usize> {
+        self.result.as_ref().ok().map(|s| s.get_n_stages())
+    }
+
+    /// Get the number of AOD row and column moves, if the compilation
+    /// succeeded
+    pub fn get_n_moves(&self) -> Option<usize> {
+        self.result.as_ref().ok().map(|s| s.get_n_moves())
+    }
+
+    /// Get the number of transfers between trap types, if the compilation
+    /// succeeded
+    pub fn get_n_transfers(&self) -> Option<usize> {
+        self.result.as_ref().ok().map(|s| s.get_n_transfers())
+    }
+}
+
+/// Results of a batch, ordered by circuit, then by configuration
+pub struct BatchResults<'a> {
+    configs: &'a [DPQA],
+    entries: Vec<BatchEntry>,
+}
+
+impl<'a> Batch<'a> {
+    /// Create a batch for the cross product of the circuits and the
+    /// configurations, using as many threads as there are cores
+    pub fn new(circuits: &'a [Circuit], configs: &'a [DPQA]) -> Batch<'a> {
+        Batch {
+            circuits,
+            configs,
+            n_threads: thread::available_parallelism().map_or(1, |n| n.get()),
+        }
+    }
+
+    /// Set the number of compilations to run at the same time
+    pub fn set_threads(&mut self, n_threads: usize) {
+        self.n_threads = n_threads.max(1);
+    }
+
+    #[cfg(feature = "z3")]
+    /// Compile every job with the Z3 solver (see `DPQA::solve`)
+    pub fn run(&self) -> BatchResults<'a> {
+        self.run_with(|dpqa, circuit| dpqa.solve(circuit))
+    }
+
+    /// Compile every job with the given function, e.g. to use another
+    /// `compiler::Compiler` built from each configuration
+    pub fn run_with(
+        &self,
+        compile: impl Fn(&DPQA, &Circuit) -> DPQAResult + Sync,
+    ) -> BatchResults<'a> {
+        let n_jobs = self.circuits.len() * self.configs.len();
+        let next_job = AtomicUsize::new(0);
+        let entries = Mutex::new(Vec::with_capacity(n_jobs));
+
+        thread::scope(|s| {
+            for _ in 0..self.n_threads.min(n_jobs) {
+                s.spawn(|| loop {
+                    let job = next_job.fetch_add(1, Ordering::Relaxed);
+                    if job >= n_jobs {
+                        break;
+                    }
+                    let (circuit, config) = (job / self.configs.len(), job % self.configs.len());
+                    let start = Instant::now();
+                    let result = compile(&self.configs[config], &self.circuits[circuit]);
+                    let entry = BatchEntry {
+                        circuit,
+                        config,
+                        result,
+                        solve_time: start.elapsed(),
+                    };
+                    entries.lock().unwrap().push(entry);
+                });
+            }
+        });
+
+        let mut entries = entries.into_inner().unwrap();
+        entries.sort_by_key(|e| (e.circuit, e.config));
+        BatchResults {
+            configs: self.configs,
+            entries,
+        }
+    }
+}
+
+impl BatchResults<'_> {
+    /// Get all the entries
+    pub fn entries(&self) -> &[BatchEntry] {
+        &self.entries
+    }
+
+    /// Get the successful entry for a circuit with the fewest sites, then
+    /// the fewest AOD lines, then the fewest stages
+    pub fn smallest_viable(&self, circuit: usize) -> Option<&BatchEntry> {
+        self.entries
+            .iter()
+            .filter(|e| e.circuit == circuit && e.result.is_ok())
+            .min_by_key(|e| {
+                let dpqa = &self.configs[e.config];
+                (
+                    dpqa.get_rows() * dpqa.get_cols(),
+                    dpqa.get_aod_rows() + dpqa.get_aod_cols(),
+                    e.get_n_stages(),
+                )
+            })
+    }
+
+    /// Write the results as CSV, with a header and one line per entry
+    pub fn write_csv(&self, mut writer: impl io::Write) -> io::Result<()> {
+        writer.write_all(self.to_csv().as_bytes())
+    }
+
+    /// Get the results as CSV, with a header and one line per entry. Columns
+    /// that only apply to successful compilations are empty otherwise.
+    pub fn to_csv(&self) -> String {
+        fn optional(value: Option<usize>) -> String {
+            value.map(|v| v.to_string()).unwrap_or_default()
+        }
+        fn quoted(error: &DPQAError) -> String {
+            format!("\"{}\"", error.to_string().replace('"', "\"\""))
+        }
+
+        let mut csv = String::from(
+            "circuit,config,rows,cols,aod_rows,aod_cols,extra_stages,\
+             success,stages,moves,transfers,solve_time_s,error\n",
+        );
+        for e in &self.entries {
+            let dpqa = &self.configs[e.config];
+            // Writing to a String cannot fail
+            let _ = writeln!(
+                csv,
+                "{},{},{},{},{},{},{},{},{},{},{},{:.6},{}",
+                e.circuit,
+                e.config,
+                dpqa.get_rows(),
+                dpqa.get_cols(),
+                dpqa.get_aod_rows(),
+                dpqa.get_aod_cols(),
+                dpqa.get_extra_stages(),
+                e.result.is_ok(),
+                optional(e.get_n_stages()),
+                optional(e.get_n_moves()),
+                optional(e.get_n_transfers()),
+                e.solve_time.as_secs_f64(),
+                e.result.as_ref().err().map(quoted).unwrap_or_default(),
+            );
+        }
+        csv
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Batch;
+    use crate::circuit::Circuit;
+    use crate::compiler::Compiler;
+    use crate::dpqa::DPQA;
+    #[cfg(feature = "z3")]
+    use crate::error::DPQAError;
+    use crate::gates::{TwoQubitGate, TwoQubitGateType::CZ};
+    use crate::greedy::GreedyCompiler;
+
+    #[test]
+    fn sweep() {
+        let mut chain = Circuit::new();
+        chain.append(TwoQubitGate::new(CZ, 0, 1));
+        chain.append(TwoQubitGate::new(CZ, 1, 2));
+        let mut pairs = Circuit::new();
+        for ii in 0..4 {
+            pairs.append(TwoQubitGate::new(CZ, 2 * ii, 2 * ii + 1));
+        }
+        let circuits = [chain, pairs];
+        let configs: Vec<DPQA> = (1..=4).map(|n| DPQA::new(n, n)).collect();
+
+        let mut batch = Batch::new(&circuits, &configs);
+        batch.set_threads(3);
+        let results = batch.run_with(|dpqa, circuit| GreedyCompiler::new(dpqa).compile(circuit));
+        assert_eq!(results.entries().len(), 8);
+        for (ii, e) in results.entries().iter().enumerate() {
+            assert_eq!((e.circuit, e.config), (ii / 4, ii % 4));
+        }
+        let smallest = |circuit| results.smallest_viable(circuit).map(|e| e.config);
+        // The greedy compiler spaces home sites by the interaction radius
+        assert_eq!(smallest(0), Some(1));
+        assert_eq!(smallest(1), Some(2));
+
+        let csv = results.to_csv();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 9);
+        assert!(lines[0].starts_with("circuit,config,rows,cols"));
+        assert!(lines[1].starts_with("0,0,1,1,1,1,0,false,,,,"));
+        assert!(
+            lines[1].ends_with("\"Grid can hold at most 2 atoms, but the circuit uses 3 qubits\"")
+        );
+        assert!(lines[2].starts_with("0,1,2,2,2,2,0,true,"));
+    }
+
+    #[cfg(feature = "z3")]
+    #[test]
+    fn solver() {
+        let mut circuit = Circuit::new();
+        circuit.append(TwoQubitGate::new(CZ, 0, 1));
+        circuit.append(TwoQubitGate::new(CZ, 1, 2));
+        circuit.append(TwoQubitGate::new(CZ, 0, 2));
+        let mut configs = vec![DPQA::new(1, 1), DPQA::new(2, 2), DPQA::new(2, 2)];
+        configs[2].set_extra_stages(1);
+
+        let circuits = [circuit];
+        let results = Batch::new(&circuits, &configs).run();
+        let entries = results.entries();
+        assert!(matches!(
+            entries[0].result,
+            Err(DPQAError::GridTooSmall { .. })
+        ));
+        assert_eq!(entries[1].get_n_stages(), Some(3));
+        assert_eq!(entries[2].get_n_stages(), Some(4));
+        assert_eq!(results.smallest_viable(0).unwrap().config, 1);
+    }
+}
@@ -1,10 +1,13 @@
-use crate::gates::{TwoQubitGate, TwoQubitGateType};
-use std::collections::HashSet;
+use crate::gates::{QubitOp, TwoQubitGate, TwoQubitGateType};
+use crate::instruction::DPQAInstruction;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
-use std::string::String;
 use std::vec::Vec;
 
-/// Circuit represented as a sequence of basic two-qubit gates.
+pub mod passes;
+
+/// Circuit represented as a sequence of basic two-qubit gates, optionally
+/// interleaved with measurements and resets.
 /// ```
 /// use dpqa_rs::circuit::Circuit;
 /// use dpqa_rs::gates::TwoQubitGate;
@@ -13,12 +16,86 @@ use std::vec::Vec;
 /// let mut circuit = Circuit::new();
 /// circuit.append(TwoQubitGate::new(CZ, 0, 1));
 /// circuit.append(TwoQubitGate::new(CX, 1, 3));
+/// circuit.measure(3, 0);
+/// circuit.reset(3);
 /// println!("{}", circuit);
 /// ```
 pub struct Circuit {
     gates: Vec<TwoQubitGate>,
     stages: Vec<Vec<usize>>,
     n_qubits: usize,
+    // Measurements and resets, each with the number of gates before it
+    ops: Vec<(usize, QubitOp)>,
+    qubit_names: HashMap<usize, String>,
+}
+
+/// Correspondence between the qubit indices of a circuit before and after
+/// `Circuit::renumber_qubits`, with the names of the original qubits
+/// ```
+/// use dpqa_rs::circuit::Circuit;
+/// use dpqa_rs::gates::{TwoQubitGate, TwoQubitGateType::CZ};
+///
+/// let mut circuit = Circuit::new();
+/// circuit.append(TwoQubitGate::new(CZ, 2, 5));
+/// circuit.set_qubit_name(5, "anc[0]");
+/// let map = circuit.renumber_qubits();
+/// assert_eq!(map.get_original(1), Some(5));
+/// assert_eq!(map.get_renumbered(2), Some(0));
+/// assert_eq!(map.label(5), "anc[0]");
+/// assert_eq!(map.label(2), "2");
+/// ```
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct QubitMap {
+    // Original index of each renumbered qubit, in increasing order
+    originals: Vec<usize>,
+    // Names by original index
+    names: HashMap<usize, String>,
+}
+
+impl QubitMap {
+    /// Get the number of renumbered qubits
+    pub fn get_n_qubits(&self) -> usize {
+        self.originals.len()
+    }
+
+    /// Check whether every qubit kept its index
+    pub fn is_identity(&self) -> bool {
+        self.originals.iter().enumerate().all(|(ii, &q)| ii == q)
+    }
+
+    /// Get the original index of a renumbered qubit
+    pub fn get_original(&self, qubit: usize) -> Option<usize> {
+        self.originals.get(qubit).copied()
+    }
+
+    /// Get the renumbered index of an original qubit, if it was used by the
+    /// circuit
+    pub fn get_renumbered(&self, original: usize) -> Option<usize> {
+        self.originals.binary_search(&original).ok()
+    }
+
+    /// Get the name of an original qubit, if one was set
+    pub fn get_name(&self, original: usize) -> Option<&str> {
+        self.names.get(&original).map(|name| name.as_str())
+    }
+
+    /// Get the name of an original qubit, or otherwise its index
+    pub fn label(&self, original: usize) -> String {
+        self.get_name(original)
+            .map_or_else(|| original.to_string(), String::from)
+    }
+
+    /// Translate an instruction on renumbered qubits back to the original
+    /// indices. Qubits outside the map keep their index.
+    pub fn restore(&self, instruction: &DPQAInstruction) -> DPQAInstruction {
+        instruction.relabel(|q| self.get_original(q).unwrap_or(q))
+    }
+}
+
+impl Default for Circuit {
+    fn default() -> Circuit {
+        Circuit::new()
+    }
 }
 
 impl Circuit {
@@ -27,6 +104,8 @@ impl Circuit {
             gates: Vec::new(),
             stages: Vec::new(),
             n_qubits: 0,
+            ops: Vec::new(),
+            qubit_names: HashMap::new(),
         }
     }
 
@@ -38,31 +117,72 @@ impl Circuit {
         self.stages.push(vec![self.gates.len() - 1]);
     }
 
+    /// Measure a qubit into a classical bit. Gates appended later that act
+    /// on the qubit are executed after the measurement.
+    pub fn measure(&mut self, qubit: usize, bit: usize) {
+        self.push_op(QubitOp::Measure { qubit, bit });
+    }
+
+    /// Reset a qubit to |0>. Gates appended later that act on the qubit are
+    /// executed after the reset.
+    pub fn reset(&mut self, qubit: usize) {
+        self.push_op(QubitOp::Reset(qubit));
+    }
+
+    fn push_op(&mut self, op: QubitOp) {
+        self.n_qubits = self.n_qubits.max(op.qubit() + 1);
+        // Operations run after the gates of a stage, so an operation before
+        // the first gate needs a stage of its own
+        if self.stages.is_empty() {
+            self.stages.push(vec![]);
+        }
+        self.ops.push((self.gates.len(), op));
+    }
+
     /// Get the number of qubits needed by the gates in this circuit
     pub fn get_n_qubits(&self) -> usize {
         self.n_qubits
     }
 
-    /// Re-number qubits so that the indices of all qubits used by `self.gates`
-    /// are consecutive integers starting from 0. Returns `true` if any indices
-    /// were changed.
-    pub fn renumber_qubits(&mut self) -> bool {
+    /// Name a qubit, e.g. after the register it came from in a QASM
+    /// program. Names are kept by `renumber_qubits`.
+    pub fn set_qubit_name(&mut self, qubit: usize, name: &str) {
+        self.qubit_names.insert(qubit, String::from(name));
+    }
+
+    /// Get the name of a qubit, if one was set
+    pub fn get_qubit_name(&self, qubit: usize) -> Option<&str> {
+        self.qubit_names.get(&qubit).map(|name| name.as_str())
+    }
+
+    /// Re-number qubits so that the indices of all qubits used by the gates
+    /// and operations are consecutive integers starting from 0. Returns the
+    /// map from the new indices to the previous ones (see
+    /// `DPQA::set_qubit_map` to restore them in compiled instructions).
+    pub fn renumber_qubits(&mut self) -> QubitMap {
         let mut seen = vec![false; self.n_qubits];
         for g in &self.gates {
             seen[g.q_ctrl] = true;
             seen[g.q_target] = true;
         }
-        if seen.iter().all(|&x| x) {
-            return false;
+        for (_, op) in &self.ops {
+            seen[op.qubit()] = true;
+        }
+        let originals: Vec<usize> = (0..self.n_qubits).filter(|&q| seen[q]).collect();
+        let names = self
+            .qubit_names
+            .iter()
+            .filter(|(&q, _)| seen.get(q).is_some_and(|&x| x))
+            .map(|(&q, name)| (q, name.clone()))
+            .collect();
+        let map = QubitMap { originals, names };
+        if map.get_n_qubits() == self.n_qubits {
+            return map;
         }
 
         let mut new_idx = vec![0; self.n_qubits];
-        let mut nn = 0;
-        for (jj, &x) in seen.iter().enumerate() {
-            if x {
-                new_idx[jj] = nn;
-                nn += 1;
-            }
+        for (nn, &jj) in map.originals.iter().enumerate() {
+            new_idx[jj] = nn;
         }
         let renumbered_gates = self
             .gates
@@ -70,28 +190,63 @@ impl Circuit {
             .map(|g| TwoQubitGate::new(g.gate_type, new_idx[g.q_ctrl], new_idx[g.q_target]))
             .collect();
         self.gates = renumbered_gates;
+        for (_, op) in self.ops.iter_mut() {
+            *op = match *op {
+                QubitOp::Measure { qubit, bit } => QubitOp::Measure {
+                    qubit: new_idx[qubit],
+                    bit,
+                },
+                QubitOp::Reset(qubit) => QubitOp::Reset(new_idx[qubit]),
+            };
+        }
+        self.qubit_names = map
+            .names
+            .iter()
+            .map(|(&q, name)| (new_idx[q], name.clone()))
+            .collect();
 
-        self.n_qubits = nn;
+        self.n_qubits = map.get_n_qubits();
 
-        true
+        map
     }
 
     /// Group gates into "stages", i.e. sets that act on different qubits
-    /// (which can be executed in parallel). Returns true if any gates were
-    /// moved into different stages.
+    /// (which can be executed in parallel). Gates are never moved before a
+    /// measurement or reset of one of their qubits. Returns true if any gates
+    /// were moved into different stages.
     pub fn recalculate_stages(&mut self) -> bool {
-        let mut new_stages: Vec<(TwoQubitGateType, HashSet<usize>)> = Vec::new();
+        // Stages start without a gate type if they are only needed to run
+        // measurements or resets
+        let mut new_stages: Vec<(Option<TwoQubitGateType>, HashSet<usize>)> = Vec::new();
         let mut qubits_used: Vec<Vec<bool>> = Vec::new();
 
+        // Last stage with a gate on each qubit, and the first stage where a
+        // gate on each qubit can go because of measurements and resets
+        let mut last_stage: Vec<Option<usize>> = vec![None; self.n_qubits];
+        let mut min_stage: Vec<usize> = vec![0; self.n_qubits];
+        let mut ops = self.ops.iter().peekable();
+
         for (ii, g) in self.gates.iter().enumerate() {
+            while let Some((_, op)) = ops.next_if(|(pos, _)| *pos <= ii) {
+                // The operation runs after the last gate on its qubit (or at
+                // the first stage), and later gates come after it
+                let q = op.qubit();
+                min_stage[q] = min_stage[q].max(last_stage[q].unwrap_or(0) + 1);
+            }
+            let earliest = min_stage[g.q_ctrl].max(min_stage[g.q_target]);
+            while new_stages.len() < earliest {
+                new_stages.push((None, HashSet::new()));
+                qubits_used.push(vec![false; self.n_qubits]);
+            }
+
             let n_s = new_stages.len();
             let mut stage_idx = n_s;
 
-            for jj in (0..n_s).rev() {
+            for jj in (earliest..n_s).rev() {
                 if !(qubits_used[jj][g.q_ctrl] || qubits_used[jj][g.q_target]) {
                     // We could add the gate here, if it has the same type
                     // as the gates already in the stage
-                    if new_stages[jj].0 == g.gate_type {
+                    if new_stages[jj].0.is_none_or(|t| t == g.gate_type) {
                         stage_idx = jj;
                     }
                 }
@@ -109,12 +264,20 @@ impl Circuit {
             }
 
             if stage_idx == n_s {
-                new_stages.push((g.gate_type, HashSet::new()));
+                new_stages.push((None, HashSet::new()));
                 qubits_used.push(vec![false; self.n_qubits]);
             }
+            new_stages[stage_idx].0 = Some(g.gate_type);
             new_stages[stage_idx].1.insert(ii);
             qubits_used[stage_idx][g.q_ctrl] = true;
             qubits_used[stage_idx][g.q_target] = true;
+            for q in [g.q_ctrl, g.q_target] {
+                last_stage[q] = Some(last_stage[q].map_or(stage_idx, |s| s.max(stage_idx)));
+            }
+        }
+        // Operations before the first gate still need a stage to run in
+        if new_stages.is_empty() && !self.ops.is_empty() {
+            new_stages.push((None, HashSet::new()));
         }
 
         let tmp = new_stages
@@ -161,25 +324,48 @@ impl Circuit {
         self.gates.iter()
     }
 
-    /// Get the nth gate in the circuit
-    pub fn get_gate(&self, n: usize) -> TwoQubitGate {
-        self.gates[n]
+    /// Get the nth gate in the circuit, or `None` if the circuit has
+    /// fewer than n + 1 gates
+    pub fn get_gate(&self, n: usize) -> Option<TwoQubitGate> {
+        self.gates.get(n).copied()
+    }
+
+    /// Get the number of measurements and resets in the circuit
+    pub fn get_n_ops(&self) -> usize {
+        self.ops.len()
+    }
+
+    /// Get an iterator over the measurements and resets in the circuit, each
+    /// with the number of gates that come before it
+    pub fn ops(&self) -> std::slice::Iter<'_, (usize, QubitOp)> {
+        self.ops.iter()
     }
 }
 
 impl fmt::Display for Circuit {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let gates_rep = self.gates.iter().fold(String::new(), |s, g| {
-            let delim = if s.is_empty() { "" } else { ", " };
-            s + delim + &format!("{}", &g)
-        });
-
-        write!(
-            f,
-            "Circuit with {} gates:\n    {}",
-            self.gates.len(),
-            gates_rep
-        )
+        // Interleave the operations with the gates
+        let mut items = Vec::new();
+        let mut ops = self.ops.iter().peekable();
+        for (ii, g) in self.gates.iter().enumerate() {
+            while let Some((_, op)) = ops.next_if(|(pos, _)| *pos <= ii) {
+                items.push(format!("{}", op));
+            }
+            items.push(format!("{}", g));
+        }
+        items.extend(ops.map(|(_, op)| format!("{}", op)));
+
+        if self.ops.is_empty() {
+            write!(f, "Circuit with {} gates:", self.gates.len())?;
+        } else {
+            write!(
+                f,
+                "Circuit with {} gates and {} measurements/resets:",
+                self.gates.len(),
+                self.ops.len()
+            )?;
+        }
+        write!(f, "\n    {}", items.join(", "))
     }
 }
 
@@ -187,7 +373,9 @@ impl fmt::Display for Circuit {
 mod tests {
     use super::Circuit;
     use super::TwoQubitGate;
+    use crate::gates::QubitOp;
     use crate::gates::TwoQubitGateType::{CX, CZ};
+    use crate::instruction::DPQAInstruction;
 
     #[test]
     fn one_gate() {
@@ -209,9 +397,35 @@ mod tests {
         let mut circuit = Circuit::new();
         circuit.append(TwoQubitGate::new(CX, 1, 2));
         circuit.append(TwoQubitGate::new(CZ, 2, 5));
+        circuit.set_qubit_name(0, "q[0]");
+        circuit.set_qubit_name(5, "q[5]");
         assert_eq!(circuit.get_n_qubits(), 6);
-        assert!(circuit.renumber_qubits());
+        let map = circuit.renumber_qubits();
+        assert!(!map.is_identity());
         assert_eq!(circuit.get_n_qubits(), 3);
+        assert_eq!(map.get_n_qubits(), 3);
+        assert_eq!(map.get_original(2), Some(5));
+        assert_eq!(map.get_original(3), None);
+        assert_eq!(map.get_renumbered(5), Some(2));
+        assert_eq!(map.get_renumbered(0), None);
+        assert_eq!(map.get_name(0), None);
+        assert_eq!(circuit.get_qubit_name(2), Some("q[5]"));
+
+        let instruction = DPQAInstruction::Gate {
+            gates: vec![TwoQubitGate::new(CZ, 1, 2)],
+            illuminated: None,
+        };
+        let restored = map.restore(&instruction);
+        assert_eq!(restored.to_string(), "Execute [CZ(2, 5)]");
+        assert_eq!(
+            restored.display_with(|q| map.label(q)).to_string(),
+            "Execute [CZ(2, q[5])]"
+        );
+        let outside = DPQAInstruction::Reset(4);
+        assert_eq!(map.restore(&outside), outside);
+
+        assert!(circuit.renumber_qubits().is_identity());
+        assert_eq!(circuit.get_qubit_name(2), Some("q[5]"));
     }
 
     #[test]
@@ -236,6 +450,55 @@ mod tests {
         assert_eq!(circuit.get_n_stages(), 3);
     }
 
+    #[test]
+    fn ops() {
+        let mut circuit = Circuit::new();
+        circuit.append(TwoQubitGate::new(CZ, 2, 3));
+        circuit.append(TwoQubitGate::new(CZ, 1, 2));
+        circuit.append(TwoQubitGate::new(CZ, 1, 4));
+        assert!(circuit.recalculate_stages());
+        assert_eq!(circuit.get_n_stages(), 2);
+
+        // The last gate can no longer move before the second one
+        let mut circuit = Circuit::new();
+        circuit.append(TwoQubitGate::new(CZ, 2, 3));
+        circuit.append(TwoQubitGate::new(CZ, 1, 2));
+        circuit.measure(1, 0);
+        circuit.append(TwoQubitGate::new(CZ, 1, 4));
+        circuit.measure(4, 1);
+        assert!(!circuit.recalculate_stages());
+        assert_eq!(circuit.get_n_stages(), 3);
+        assert_eq!(circuit.get_n_ops(), 2);
+        assert_eq!(
+            format!("{}", circuit),
+            "Circuit with 3 gates and 2 measurements/resets:\n    \
+             CZ(2, 3), CZ(1, 2), Measure(1 -> c0), CZ(1, 4), Measure(4 -> c1)"
+        );
+
+        // A reset before the first gate needs a stage of its own
+        let mut circuit = Circuit::new();
+        circuit.reset(0);
+        circuit.append(TwoQubitGate::new(CZ, 0, 1));
+        circuit.append(TwoQubitGate::new(CZ, 2, 3));
+        assert_eq!(circuit.get_n_stages(), 3);
+        assert!(circuit.recalculate_stages());
+        assert_eq!(circuit.get_n_stages(), 2);
+    }
+
+    #[test]
+    fn renumber_ops() {
+        let mut circuit = Circuit::new();
+        circuit.append(TwoQubitGate::new(CZ, 1, 3));
+        circuit.measure(5, 0);
+        assert_eq!(circuit.get_n_qubits(), 6);
+        assert!(!circuit.renumber_qubits().is_identity());
+        assert_eq!(circuit.get_n_qubits(), 3);
+        assert_eq!(
+            circuit.ops().next(),
+            Some(&(1, QubitOp::Measure { qubit: 2, bit: 0 }))
+        );
+    }
+
     #[test]
     fn restage_3() {
         let mut circuit = Circuit::new();
@@ -244,4 +507,15 @@ mod tests {
         assert_eq!(circuit.get_n_stages(), 2);
         assert!(!circuit.recalculate_stages());
     }
+
+    #[test]
+    fn restage_4() {
+        // CZ(1, 2) cannot move before CX(0, 1)
+        let mut circuit = Circuit::new();
+        circuit.append(TwoQubitGate::new(CZ, 3, 4));
+        circuit.append(TwoQubitGate::new(CX, 0, 1));
+        circuit.append(TwoQubitGate::new(CZ, 1, 2));
+        circuit.recalculate_stages();
+        assert_eq!(circuit.get_n_stages(), 3);
+    }
 }
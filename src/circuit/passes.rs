@@ -0,0 +1,257 @@
+use super::{Circuit, QubitMap};
+use crate::gates::{TwoQubitGate, TwoQubitGateType};
+use std::fmt;
+
+/// Changes made to a circuit by a simplification pass
+/// ```
+/// use dpqa_rs::circuit::{passes, Circuit};
+/// use dpqa_rs::gates::{TwoQubitGate, TwoQubitGateType::{CX, CZ}};
+///
+/// let mut circuit = Circuit::new();
+/// circuit.append(TwoQubitGate::new(CX, 0, 1));
+/// circuit.append(TwoQubitGate::new(CZ, 2, 3));
+/// circuit.append(TwoQubitGate::new(CX, 0, 1));
+/// circuit.append(TwoQubitGate::new(CZ, 3, 4));
+///
+/// let report = passes::cancel_gates(&mut circuit);
+/// assert_eq!(report.cancelled, [(0, 2)]);
+/// let report = passes::remove_idle_qubits(&mut circuit);
+/// assert_eq!(report.qubit_map.unwrap().get_original(0), Some(2));
+/// assert_eq!(circuit.get_n_qubits(), 3);
+/// ```
+#[derive(PartialEq, Eq, Clone, Debug, Default)]
+pub struct PassReport {
+    /// Pairs of gates that were removed because they cancel out, as indices
+    /// in the circuit before the pass
+    pub cancelled: Vec<(usize, usize)>,
+    /// Map from the qubit indices after the pass to those before, if any
+    /// qubits were removed
+    pub qubit_map: Option<QubitMap>,
+}
+
+impl PassReport {
+    /// Check whether the pass changed the circuit
+    pub fn changed(&self) -> bool {
+        !self.cancelled.is_empty() || self.qubit_map.is_some()
+    }
+}
+
+impl fmt::Display for PassReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Removed {} pairs of gates", self.cancelled.len())?;
+        if let Some(map) = &self.qubit_map {
+            write!(f, ", kept {} qubits", map.get_n_qubits())?;
+        }
+        Ok(())
+    }
+}
+
+/// Remove pairs of identical gates that cancel out, since CZ and CX are
+/// their own inverse. The second gate of a pair may come after gates that
+/// commute with it (see `TwoQubitGate::commutes_with`), but not after a
+/// measurement or reset of one of its qubits. Stages that only contained
+/// removed gates are dropped; call `Circuit::recalculate_stages` to regroup
+/// the remaining gates.
+pub fn cancel_gates(circuit: &mut Circuit) -> PassReport {
+    cancel_pairs(circuit, |g0, g1| g0 == g1)
+}
+
+/// Merge CZ gates on the same pair of qubits, in either order of control
+/// and target, since CZ is symmetric. As with `cancel_gates`, the gates may
+/// be separated by gates that commute with them. An even number of merged
+/// gates cancels out, and an odd number leaves a single gate.
+pub fn merge_phase_gates(circuit: &mut Circuit) -> PassReport {
+    cancel_pairs(circuit, |g0, g1| {
+        g0.gate_type == TwoQubitGateType::CZ
+            && g1.gate_type == TwoQubitGateType::CZ
+            && !g0.parallel_with(g1)
+            && g0.q_ctrl.min(g0.q_target) == g1.q_ctrl.min(g1.q_target)
+            && g0.q_ctrl.max(g0.q_target) == g1.q_ctrl.max(g1.q_target)
+    })
+}
+
+/// Remove qubits not used by any gate, measurement or reset, e.g. after
+/// other passes cancelled all their gates (see `Circuit::renumber_qubits`)
+pub fn remove_idle_qubits(circuit: &mut Circuit) -> PassReport {
+    let map = circuit.renumber_qubits();
+    PassReport {
+        cancelled: vec![],
+        qubit_map: (!map.is_identity()).then_some(map),
+    }
+}
+
+/// Remove pairs of gates for which `cancels` holds, and that can be brought
+/// next to each other by commuting the second gate backwards
+fn cancel_pairs(
+    circuit: &mut Circuit,
+    cancels: impl Fn(&TwoQubitGate, &TwoQubitGate) -> bool,
+) -> PassReport {
+    let gates = &circuit.gates;
+    // Qubits measured or reset just before each gate
+    let mut barriers = vec![vec![]; gates.len() + 1];
+    for (pos, op) in &circuit.ops {
+        barriers[*pos].push(op.qubit());
+    }
+
+    let mut removed = vec![false; gates.len()];
+    let mut cancelled = vec![];
+    for (jj, g) in gates.iter().enumerate() {
+        for ii in (0..jj).rev() {
+            if barriers[ii + 1]
+                .iter()
+                .any(|&q| q == g.q_ctrl || q == g.q_target)
+            {
+                break;
+            }
+            if removed[ii] {
+                continue;
+            }
+            if cancels(&gates[ii], g) {
+                removed[ii] = true;
+                removed[jj] = true;
+                cancelled.push((ii, jj));
+                break;
+            }
+            if !gates[ii].commutes_with(g) {
+                break;
+            }
+        }
+    }
+    cancelled.sort();
+
+    if !cancelled.is_empty() {
+        remove_gates(circuit, &removed);
+    }
+    PassReport {
+        cancelled,
+        qubit_map: None,
+    }
+}
+
+/// Remove the given gates, keeping the measurements and resets in place
+fn remove_gates(circuit: &mut Circuit, removed: &[bool]) {
+    // Number of gates kept before each gate
+    let mut new_idx = Vec::with_capacity(removed.len() + 1);
+    let mut n_kept = 0;
+    for &r in removed {
+        new_idx.push(n_kept);
+        n_kept += usize::from(!r);
+    }
+    new_idx.push(n_kept);
+
+    let mut ii = 0;
+    circuit.gates.retain(|_| {
+        ii += 1;
+        !removed[ii - 1]
+    });
+    for (pos, _) in circuit.ops.iter_mut() {
+        *pos = new_idx[*pos];
+    }
+    circuit.stages = circuit
+        .stages
+        .iter()
+        .filter(|stage| stage.is_empty() || stage.iter().any(|&g| !removed[g]))
+        .map(|stage| {
+            stage
+                .iter()
+                .filter(|&&g| !removed[g])
+                .map(|&g| new_idx[g])
+                .collect()
+        })
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cancel_gates, merge_phase_gates, remove_idle_qubits};
+    use crate::circuit::Circuit;
+    use crate::gates::TwoQubitGate;
+    use crate::gates::TwoQubitGateType::{CX, CZ};
+
+    #[test]
+    fn cancel() {
+        // The CZ commutes with the CXs, but the CX(2, 0) does not
+        let mut circuit = Circuit::new();
+        circuit.append(TwoQubitGate::new(CX, 0, 1));
+        circuit.append(TwoQubitGate::new(CZ, 0, 2));
+        circuit.append(TwoQubitGate::new(CX, 0, 1));
+        circuit.append(TwoQubitGate::new(CX, 2, 0));
+        circuit.append(TwoQubitGate::new(CZ, 0, 2));
+        circuit.append(TwoQubitGate::new(CZ, 0, 2));
+        assert_eq!(circuit.get_n_stages(), 6);
+
+        let report = cancel_gates(&mut circuit);
+        assert_eq!(report.cancelled, [(0, 2), (4, 5)]);
+        assert!(report.changed());
+        assert_eq!(report.to_string(), "Removed 2 pairs of gates");
+        assert_eq!(
+            circuit.iter().copied().collect::<Vec<_>>(),
+            [TwoQubitGate::new(CZ, 0, 2), TwoQubitGate::new(CX, 2, 0)]
+        );
+        assert_eq!(circuit.get_n_stages(), 2);
+        assert_eq!(circuit.get_gate_ordering(), [(0, 1)]);
+        assert!(!cancel_gates(&mut circuit).changed());
+    }
+
+    #[test]
+    fn barriers() {
+        let mut circuit = Circuit::new();
+        circuit.append(TwoQubitGate::new(CZ, 0, 1));
+        circuit.measure(1, 0);
+        circuit.append(TwoQubitGate::new(CZ, 0, 1));
+        circuit.append(TwoQubitGate::new(CX, 2, 3));
+        circuit.reset(4);
+        circuit.append(TwoQubitGate::new(CX, 2, 3));
+        circuit.append(TwoQubitGate::new(CZ, 1, 4));
+
+        let report = cancel_gates(&mut circuit);
+        assert_eq!(report.cancelled, [(2, 3)]);
+        assert_eq!(circuit.get_n_two_qubit_gates(), 3);
+        assert_eq!(
+            circuit.to_string(),
+            "Circuit with 3 gates and 2 measurements/resets:\n    \
+             CZ(0, 1), Measure(1 -> c0), CZ(0, 1), Reset(4), CZ(1, 4)"
+        );
+    }
+
+    #[test]
+    fn merge() {
+        let mut circuit = Circuit::new();
+        circuit.append(TwoQubitGate::new(CZ, 0, 1));
+        circuit.append(TwoQubitGate::new(CZ, 1, 2));
+        circuit.append(TwoQubitGate::new(CZ, 1, 0));
+        circuit.append(TwoQubitGate::new(CX, 1, 0));
+        circuit.append(TwoQubitGate::new(CX, 0, 1));
+        circuit.recalculate_stages();
+
+        // Only gates that are exactly the same cancel
+        assert!(!cancel_gates(&mut circuit).changed());
+        let report = merge_phase_gates(&mut circuit);
+        assert_eq!(report.cancelled, [(0, 2)]);
+        assert_eq!(circuit.get_n_two_qubit_gates(), 3);
+        circuit.recalculate_stages();
+        assert_eq!(circuit.get_n_stages(), 3);
+    }
+
+    #[test]
+    fn idle_qubits() {
+        let mut circuit = Circuit::new();
+        circuit.append(TwoQubitGate::new(CZ, 0, 1));
+        circuit.append(TwoQubitGate::new(CX, 1, 2));
+        circuit.append(TwoQubitGate::new(CZ, 1, 0));
+        circuit.measure(2, 0);
+        assert!(!remove_idle_qubits(&mut circuit).changed());
+
+        merge_phase_gates(&mut circuit);
+        let report = remove_idle_qubits(&mut circuit);
+        assert!(report.cancelled.is_empty());
+        assert_eq!(
+            report.to_string(),
+            "Removed 0 pairs of gates, kept 2 qubits"
+        );
+        let map = report.qubit_map.unwrap();
+        assert_eq!(map.get_original(0), Some(1));
+        assert_eq!(map.get_original(1), Some(2));
+        assert_eq!(circuit.get_n_qubits(), 2);
+    }
+}
@@ -0,0 +1,20 @@
+use crate::{circuit::Circuit, result::DPQAResult};
+
+#[cfg(feature = "z3")]
+use crate::dpqa::DPQA;
+
+/// Common interface of the backends that compile a circuit into a schedule
+/// for a given architecture
+pub trait Compiler {
+    /// Compile the circuit, returning the schedule or the reason it could
+    /// not be compiled
+    fn compile(&self, circuit: &Circuit) -> DPQAResult;
+}
+
+/// Optimal compilation with the Z3 solver (see `DPQA::solve`)
+#[cfg(feature = "z3")]
+impl Compiler for DPQA {
+    fn compile(&self, circuit: &Circuit) -> DPQAResult {
+        self.solve(circuit)
+    }
+}
@@ -1,17 +1,129 @@
 use crate::{
-    circuit::Circuit, instruction::DPQAInstruction, result::DPQAResult, variables::DPQAVars,
+    circuit::{Circuit, QubitMap},
+    error::DPQAError,
+    progress::{SolveEvent, SolveObserver},
+    result::Solution,
 };
-use std::collections::HashMap;
+use itertools::Itertools;
 use std::fmt;
-use z3::{Config, Context, Optimize, SatResult};
+use std::sync::Arc;
+use std::time::Duration;
 
-/// DPQA solver
+#[cfg(feature = "z3")]
+use crate::{
+    backend::{parse_model, ConstraintSink, CountingSink, LexOptimize, SmtLib2Writer},
+    portfolio::Cancel,
+    result::DPQAResult,
+    schedule::{AtomState, Schedule},
+    variables::DPQAVars,
+};
+#[cfg(feature = "z3")]
+use std::time::Instant;
+#[cfg(feature = "z3")]
+use z3::{Config, Context, SatResult};
+
+/// Which sites can be illuminated by the Rydberg laser that executes gates
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum Addressing {
+    /// A global pulse illuminates the whole grid, so all co-located atoms
+    /// interact
+    Global,
+    /// Individually addressable beams cover the given (x, y) sites
+    Sites(Vec<(u64, u64)>),
+    /// Individually addressable beams cover every site in the given rows
+    Rows(Vec<u64>),
+    /// Individually addressable beams cover every site in the given columns
+    Cols(Vec<u64>),
+}
+
+/// Configuration the atoms must be in at the end of the schedule
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum FinalLayout {
+    /// No constraint on the final configuration
+    Any,
+    /// All atoms end in SLM traps
+    AllSLM,
+    /// All atoms end in the same positions and traps as they were
+    /// initialized in
+    Initial,
+    /// Each qubit ends in the SLM trap at the given (x, y) site
+    Custom(Vec<(u64, u64)>),
+}
+
+/// How positions, AOD indices and stages are encoded in the solver
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Encoding {
+    /// Unbounded integers, constrained to the grid
+    Int,
+    /// Bit-vectors just wide enough for each range of values
+    BitVec,
+    /// One Boolean per possible value, which turns the constraints into a
+    /// pure SAT problem
+    OneHot,
+}
+
+/// Limits on the grids tried by `DPQA::find_min_grid`
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct GridLimits {
+    /// Largest number of rows of SLM traps
+    pub max_rows: u64,
+    /// Largest number of columns of SLM traps
+    pub max_cols: u64,
+    /// Largest ratio between the longer and the shorter side of the grid
+    pub max_aspect_ratio: Option<u64>,
+    /// Also search for the smallest AOD grid, rather than using an AOD grid
+    /// of the same size as the SLM grid
+    pub search_aod: bool,
+}
+
+impl GridLimits {
+    /// Allow any grid up to the given dimensions, with an AOD grid of the
+    /// same size
+    pub fn new(max_rows: u64, max_cols: u64) -> GridLimits {
+        GridLimits {
+            max_rows,
+            max_cols,
+            max_aspect_ratio: None,
+            search_aod: false,
+        }
+    }
+}
+
+/// Previous schedule used to guide the solver
+#[cfg(feature = "z3")]
+struct WarmStart<'a> {
+    previous: &'a Schedule,
+    /// Qubits whose trajectories in `previous` should be kept
+    keep: Vec<usize>,
+    /// Whether the gates should be executed at the same stages as in
+    /// `previous`
+    keep_gate_times: bool,
+    /// Atoms available at the start, if restricted
+    loaded: Option<Vec<AtomState>>,
+}
+
+/// DPQA architecture, and options for compiling circuits on it with the Z3
+/// solver (see also `greedy::GreedyCompiler`)
+#[cfg_attr(not(feature = "z3"), allow(dead_code))]
+#[derive(Clone)]
 pub struct DPQA {
     rows: u64,
     cols: u64,
     aod_rows: u64,
     aod_cols: u64,
     extra_stages: usize,
+    timeout: Option<Duration>,
+    random_seed: Option<u32>,
+    interaction_radius: u64,
+    addressing: Addressing,
+    readout_zone: Option<Vec<u64>>,
+    cyclic: bool,
+    final_layout: FinalLayout,
+    max_return_stages: usize,
+    encoding: Encoding,
+    symmetry_breaking: bool,
+    observer: Option<Arc<SolveObserver>>,
+    qubit_map: Option<QubitMap>,
 }
 
 impl DPQA {
@@ -22,13 +134,7 @@ impl DPQA {
     /// println!("{}", dpqa_compiler);
     /// ```
     pub fn new(rows: u64, cols: u64) -> DPQA {
-        DPQA {
-            rows: rows,
-            cols: cols,
-            aod_rows: rows,
-            aod_cols: cols,
-            extra_stages: 0,
-        }
+        DPQA::new_aod(rows, cols, rows, cols)
     }
 
     /// Create a new DPQA solver by specifying the grid, potentially
@@ -40,14 +146,43 @@ impl DPQA {
             aod_rows,
             aod_cols,
             extra_stages: 0,
+            timeout: None,
+            random_seed: None,
+            interaction_radius: 1,
+            addressing: Addressing::Global,
+            readout_zone: None,
+            cyclic: false,
+            final_layout: FinalLayout::Any,
+            max_return_stages: 3,
+            encoding: Encoding::Int,
+            symmetry_breaking: false,
+            observer: None,
+            qubit_map: None,
         }
     }
 
+    #[cfg(feature = "z3")]
     /// Set up constraints for the given architecture and circuit, then attempt
     /// to solve.
+    ///
+    /// The instructions are deterministic for a given solver model:
+    /// * all atoms are initialized in qubit order,
+    /// * at each stage, AOD column moves are followed by AOD row moves, then
+    ///   by transfers (in qubit order), then by gates (in circuit order),
+    ///   then by measurements and resets (in circuit order),
+    /// * lines moving towards higher coordinates move first, in decreasing
+    ///   order of AOD index, followed by lines moving towards lower
+    ///   coordinates, in increasing order of AOD index (see
+    ///   `sequencing::sequence_moves`). Executing the moves one at a time in
+    ///   this order never makes AOD lines cross or stack too closely.
+    ///
+    /// The same information is available grouped by stage from
+    /// `Solution::schedule`.
+    ///
+    /// Z3 itself is deterministic for identical inputs and parameters; see
+    /// `set_random_seed` to pin its seed explicitly.
     /// ```
     /// use dpqa_rs::dpqa::DPQA;
-    /// use dpqa_rs::result::DPQAResult;
     /// use dpqa_rs::circuit::Circuit;
     /// use dpqa_rs::gates::{TwoQubitGate, TwoQubitGateType::CZ};
     ///
@@ -60,114 +195,733 @@ impl DPQA {
     /// circuit.recalculate_stages();
     ///
     /// let dpqa = DPQA::new(2, 2);
-    /// let result = dpqa.solve(&circuit);
-    ///
-    /// if let DPQAResult::Succeeded(instructions) = result {
-    ///     for x in &instructions {
-    ///         println!("{}", x);
+    /// match dpqa.solve(&circuit) {
+    ///     Ok(solution) => {
+    ///         for x in solution.instructions() {
+    ///             println!("{}", x);
+    ///         }
     ///     }
-    /// } else {
-    ///     println!("Could not compile with the given constraints");
+    ///     Err(e) => println!("Could not compile: {}", e),
     /// }
     /// ```
     pub fn solve(&self, circuit: &Circuit) -> DPQAResult {
-        let cfg = Config::new();
-        let ctx = Context::new(&cfg);
-        let solver = Optimize::new(&ctx);
-        let n_stages = circuit.get_n_stages() + self.extra_stages;
-
-        let vars = DPQAVars::new(
-            &ctx,
-            circuit,
-            self.rows,
-            self.cols,
-            self.aod_rows,
-            self.aod_cols,
-            n_stages,
-        );
-        vars.set_constraints(&solver);
-        vars.set_optimization(&solver);
+        self.check_feasible(circuit)?;
+        self.solve_warm(circuit, self.extra_stages, None, None)
+    }
 
-        if solver.check(&[]) == SatResult::Sat {
-            let n_qubits = circuit.get_n_qubits();
-
-            let vals = vars.eval(&solver);
-            let mut instructions: Vec<DPQAInstruction> = Vec::new();
-            let n_gates = circuit.get_n_two_qubit_gates();
-            let mut gate_idx = 0;
-
-            for jj in 0..n_stages {
-                if jj == 0 {
-                    // Get initial state
-                    for (ii, xy) in vals.xy.iter().enumerate() {
-                        instructions.push(DPQAInstruction::Init {
-                            qubit: ii,
-                            x: xy[0].0,
-                            y: xy[0].1,
-                            in_aod: vals.aod[ii][0],
-                        });
-                    }
-                } else {
-                    // Check for AOD grid moves
-                    let mut moves_x: HashMap<(u64, u64), Vec<usize>> = HashMap::new();
-                    for ii in 0..n_qubits {
-                        let x_prev = vals.xy[ii][jj - 1].0;
-                        let x_curr = vals.xy[ii][jj].0;
-                        let c_prev = vals.cr[ii][jj - 1].0;
-                        let c_curr = vals.cr[ii][jj].0;
-                        if x_curr != x_prev && c_curr == c_prev {
-                            moves_x.entry((x_prev, x_curr)).or_default().push(ii);
-                        }
-                    }
-                    for (mv, qubits) in moves_x.iter() {
-                        instructions.push(DPQAInstruction::MoveAODCol {
-                            qubits: qubits.clone(),
-                            x_from: mv.0,
-                            x_to: mv.1,
-                        });
-                    }
+    #[cfg(feature = "z3")]
+    /// Solve, giving up when cancelled by another solver of a portfolio
+    pub(crate) fn solve_cancellable(&self, circuit: &Circuit, cancel: &Cancel) -> DPQAResult {
+        self.check_feasible(circuit)?;
+        self.solve_warm(circuit, self.extra_stages, None, Some(cancel))
+    }
 
-                    let mut moves_y: HashMap<(u64, u64), Vec<usize>> = HashMap::new();
-                    for ii in 0..n_qubits {
-                        let y_prev = vals.xy[ii][jj - 1].1;
-                        let y_curr = vals.xy[ii][jj].1;
-                        let r_prev = vals.cr[ii][jj - 1].1;
-                        let r_curr = vals.cr[ii][jj].1;
-                        if y_curr != y_prev && r_curr == r_prev {
-                            moves_y.entry((y_prev, y_curr)).or_default().push(ii);
-                        }
-                    }
-                    for (mv, qubits) in moves_y.iter() {
-                        instructions.push(DPQAInstruction::MoveAODRow {
-                            qubits: qubits.clone(),
-                            y_from: mv.0,
-                            y_to: mv.1,
-                        });
-                    }
+    #[cfg(feature = "z3")]
+    /// Solve, using a schedule (e.g. from a previous compilation of a similar
+    /// circuit, a heuristic, or a smaller grid) as a hint. The solver first
+    /// checks whether the positions, traps and gate stages of the hint work
+    /// as they are, which is reported by `Solution::hint_feasible`. It then
+    /// optimizes as `solve` does, and keeps as much of the hint as it can
+    /// among the best schedules.
+    /// ```
+    /// use dpqa_rs::circuit::Circuit;
+    /// use dpqa_rs::dpqa::DPQA;
+    /// use dpqa_rs::gates::{TwoQubitGate, TwoQubitGateType::CZ};
+    ///
+    /// let mut circuit = Circuit::new();
+    /// circuit.append(TwoQubitGate::new(CZ, 0, 1));
+    /// circuit.append(TwoQubitGate::new(CZ, 1, 2));
+    ///
+    /// let dpqa = DPQA::new(2, 2);
+    /// let solution = dpqa.solve(&circuit).unwrap();
+    ///
+    /// circuit.append(TwoQubitGate::new(CZ, 0, 2));
+    /// let updated = dpqa.solve_with_hint(&circuit, solution.schedule()).unwrap();
+    /// println!("Hint was feasible: {:?}", updated.hint_feasible());
+    /// ```
+    pub fn solve_with_hint(&self, circuit: &Circuit, hint: &Schedule) -> DPQAResult {
+        self.check_feasible(circuit)?;
+        let warm_start = WarmStart {
+            previous: hint,
+            keep: (0..circuit.get_n_qubits()).collect(),
+            keep_gate_times: true,
+            loaded: None,
+        };
+        self.solve_warm(circuit, self.extra_stages, Some(&warm_start), None)
+    }
 
-                    // Check for AOD to SLM moves
-                    for ii in 0..n_qubits {
-                        if !vals.aod[ii][jj] && vals.aod[ii][jj - 1] {
-                            instructions.push(DPQAInstruction::MoveToSLM(ii));
-                        } else if vals.aod[ii][jj] && !vals.aod[ii][jj - 1] {
-                            instructions.push(DPQAInstruction::MoveToAOD(ii));
-                        }
-                    }
+    #[cfg(feature = "z3")]
+    /// Find the smallest grid within the limits on which the circuit
+    /// compiles, with the other options of this solver (including the number
+    /// of extra stages). Grids are tried in order of number of sites, then
+    /// of AOD lines. Grids ruled out by `check_feasible`, or too small to
+    /// keep the qubits of different gate types apart, are skipped without
+    /// calling the solver. Returns the solver for the grid with its solution,
+    /// or otherwise the error for the largest grid (or `Timeout` if the
+    /// solver timed out on any grid).
+    /// ```
+    /// use dpqa_rs::circuit::Circuit;
+    /// use dpqa_rs::dpqa::{GridLimits, DPQA};
+    /// use dpqa_rs::gates::{TwoQubitGate, TwoQubitGateType::CZ};
+    ///
+    /// let mut circuit = Circuit::new();
+    /// circuit.append(TwoQubitGate::new(CZ, 0, 1));
+    /// circuit.append(TwoQubitGate::new(CZ, 1, 2));
+    ///
+    /// let (dpqa, solution) = DPQA::new(1, 1)
+    ///     .find_min_grid(&circuit, &GridLimits::new(4, 4))
+    ///     .unwrap();
+    /// assert!(dpqa.get_rows() * dpqa.get_cols() <= 2);
+    /// assert_eq!(solution.verify(&circuit), Ok(()));
+    /// ```
+    pub fn find_min_grid(
+        &self,
+        circuit: &Circuit,
+        limits: &GridLimits,
+    ) -> Result<(DPQA, Solution), DPQAError> {
+        let mut grids = vec![];
+        for (rows, cols) in (1..=limits.max_rows).cartesian_product(1..=limits.max_cols) {
+            if limits
+                .max_aspect_ratio
+                .is_some_and(|ratio| rows.max(cols) > ratio * rows.min(cols))
+            {
+                continue;
+            }
+            if limits.search_aod {
+                for (aod_rows, aod_cols) in (1..=rows).cartesian_product(1..=cols) {
+                    grids.push((rows, cols, aod_rows, aod_cols));
                 }
+            } else {
+                grids.push((rows, cols, rows, cols));
+            }
+        }
+        grids.sort_by_key(|&(rows, cols, aod_rows, aod_cols)| {
+            (rows * cols, aod_rows + aod_cols, rows, cols)
+        });
 
-                // Report gates
-                let mut gates_run = Vec::new();
-                while gate_idx < n_gates && vals.t[gate_idx] as usize == jj {
-                    gates_run.push(circuit.get_gate(gate_idx));
-                    gate_idx += 1;
-                }
-                if !gates_run.is_empty() {
-                    instructions.push(DPQAInstruction::Gate(gates_run));
+        let min_sites = self.min_sites_separated(circuit);
+        let mut timed_out = false;
+        let mut error = DPQAError::InvalidArchitecture(String::from("no grid within the limits"));
+        for (rows, cols, aod_rows, aod_cols) in grids {
+            let mut dpqa = self.clone();
+            (dpqa.rows, dpqa.cols) = (rows, cols);
+            (dpqa.aod_rows, dpqa.aod_cols) = (aod_rows, aod_cols);
+
+            let result = match dpqa.check_feasible(circuit) {
+                Ok(()) if rows * cols < min_sites => Err(DPQAError::Unsatisfiable),
+                Ok(()) => dpqa.solve_warm(circuit, dpqa.extra_stages, None, None),
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(solution) => return Ok((dpqa, solution)),
+                Err(DPQAError::Timeout) => timed_out = true,
+                Err(
+                    DPQAError::InvalidArchitecture(_)
+                    | DPQAError::GridTooSmall { .. }
+                    | DPQAError::Unsatisfiable,
+                ) => {}
+                Err(e) => return Err(e),
+            }
+            error = result.unwrap_err();
+        }
+        Err(if timed_out { DPQAError::Timeout } else { error })
+    }
+
+    /// Write the constraints and objectives used by `solve` as an SMT-LIB2
+    /// script, to run with another solver or share the instance. If a final
+    /// layout is set, the script allows for the maximum number of return
+    /// stages. Read the solver's output with `from_smtlib2_model`.
+    /// ```
+    /// use dpqa_rs::circuit::Circuit;
+    /// use dpqa_rs::dpqa::DPQA;
+    /// use dpqa_rs::gates::{TwoQubitGate, TwoQubitGateType::CZ};
+    ///
+    /// let mut circuit = Circuit::new();
+    /// circuit.append(TwoQubitGate::new(CZ, 0, 1));
+    ///
+    /// let script = DPQA::new(1, 2).to_smtlib2(&circuit).unwrap();
+    /// assert!(script.contains("(declare-const x_q0_t0 Int)"));
+    /// ```
+    #[cfg(feature = "z3")]
+    pub fn to_smtlib2(&self, circuit: &Circuit) -> Result<String, DPQAError> {
+        self.check_feasible(circuit)?;
+        let ctx = Context::new(&Config::new());
+        let writer = SmtLib2Writer::new(&ctx);
+        let (n_stages, n_return_stages) = self.exported_stages(circuit);
+        let vars = DPQAVars::new(&ctx, circuit, self, n_stages, n_return_stages);
+        vars.set_constraints(&writer, &mut |_, _| {});
+        if self.symmetry_breaking {
+            vars.constraint_symmetry_breaking(&writer);
+        }
+        vars.set_optimization(&writer);
+        Ok(writer.to_string())
+    }
+
+    /// Build the solution from the output of an external solver run on the
+    /// script from `to_smtlib2` (with the same circuit and options): the
+    /// result of `(check-sat)`, then the model as a list of `define-fun`s.
+    /// Variables missing from the model are taken to be 0 or false, and the
+    /// values are checked against the constraints.
+    #[cfg(feature = "z3")]
+    pub fn from_smtlib2_model(&self, circuit: &Circuit, output: &str) -> DPQAResult {
+        self.check_feasible(circuit)?;
+        let model = parse_model(output)?;
+        let ctx = Context::new(&Config::new());
+        let (n_stages, n_return_stages) = self.exported_stages(circuit);
+        let vars = DPQAVars::new(&ctx, circuit, self, n_stages, n_return_stages);
+        let vals = vars.eval_external(&model)?;
+        let schedule = Schedule::from_values(circuit, &vals, &self.addressing)?;
+        Ok(self.labelled(Solution::with_return_stages(schedule, n_return_stages)))
+    }
+
+    /// Number of stages for gates and return stages in exported instances
+    #[cfg(feature = "z3")]
+    fn exported_stages(&self, circuit: &Circuit) -> (usize, usize) {
+        let n_return_stages = match self.final_layout {
+            FinalLayout::Any => 0,
+            _ => self.max_return_stages,
+        };
+        (circuit.get_n_stages() + self.extra_stages, n_return_stages)
+    }
+
+    /// Recompile a circuit after atoms were lost, starting from a previous
+    /// schedule for it. `lost` lists the qubits of `previous` whose atoms are
+    /// missing, and `spares` the SLM sites holding spare atoms.
+    ///
+    /// Every qubit starts on a surviving atom (at its position and trap in
+    /// `previous`) or on a spare atom, so the `Init` instructions of the new
+    /// schedule give the mapping from qubits to atoms. The solver first tries
+    /// to keep the whole trajectory of every surviving qubit, and otherwise
+    /// keeps as many of them as it can, which is usually much faster than
+    /// compiling from scratch. If spare atoms are needed, one stage may be
+    /// added to move them into place.
+    /// ```
+    /// use dpqa_rs::circuit::Circuit;
+    /// use dpqa_rs::dpqa::DPQA;
+    /// use dpqa_rs::gates::{TwoQubitGate, TwoQubitGateType::CZ};
+    ///
+    /// let mut circuit = Circuit::new();
+    /// circuit.append(TwoQubitGate::new(CZ, 0, 1));
+    /// circuit.append(TwoQubitGate::new(CZ, 1, 2));
+    ///
+    /// let dpqa = DPQA::new(3, 3);
+    /// let solution = dpqa.solve(&circuit).unwrap();
+    /// // The atom of qubit 1 is lost, and there is a spare atom at (2, 2)
+    /// match dpqa.recompile_after_loss(&circuit, solution.schedule(), &[1], &[(2, 2)]) {
+    ///     Ok(recovered) => assert_eq!(recovered.verify(&circuit), Ok(())),
+    ///     Err(e) => println!("Could not recompile: {}", e),
+    /// }
+    /// ```
+    #[cfg(feature = "z3")]
+    pub fn recompile_after_loss(
+        &self,
+        circuit: &Circuit,
+        previous: &Schedule,
+        lost: &[usize],
+        spares: &[(u64, u64)],
+    ) -> DPQAResult {
+        self.check_feasible(circuit)?;
+
+        let initial = previous
+            .iter()
+            .next()
+            .map(|stage| stage.atoms.as_slice())
+            .unwrap_or_default();
+        if let Some(q) = lost.iter().find(|&&q| q >= initial.len()) {
+            return Err(DPQAError::InvalidSchedule(format!(
+                "lost qubit {} is not in the previous schedule",
+                q
+            )));
+        }
+        if spares
+            .iter()
+            .any(|&(x, y)| x >= self.cols || y >= self.rows)
+        {
+            return Err(DPQAError::InvalidArchitecture(format!(
+                "spare sites {:?} must be within the grid",
+                spares
+            )));
+        }
+
+        let surviving: Vec<usize> = (0..initial.len()).filter(|q| !lost.contains(q)).collect();
+        let loaded: Vec<AtomState> = surviving
+            .iter()
+            .map(|&q| initial[q])
+            .chain(spares.iter().map(|&(x, y)| AtomState { x, y, aod: None }))
+            .collect();
+        let n_qubits = circuit.get_n_qubits();
+        if loaded.len() < n_qubits {
+            return Err(DPQAError::GridTooSmall {
+                n_qubits,
+                capacity: loaded.len() as u64,
+            });
+        }
+
+        let warm_start = WarmStart {
+            previous,
+            keep: surviving.into_iter().filter(|&q| q < n_qubits).collect(),
+            keep_gate_times: false,
+            loaded: Some(loaded),
+        };
+        // Spare atoms may need one more stage to reach their first gate
+        match self.solve_warm(circuit, self.extra_stages, Some(&warm_start), None) {
+            Err(DPQAError::Unsatisfiable) => {
+                self.solve_warm(circuit, self.extra_stages + 1, Some(&warm_start), None)
+            }
+            result => result,
+        }
+    }
+
+    #[cfg(feature = "z3")]
+    /// Solve, with as few return stages as possible to reach the final
+    /// layout
+    fn solve_warm(
+        &self,
+        circuit: &Circuit,
+        extra_stages: usize,
+        warm_start: Option<&WarmStart>,
+        cancel: Option<&Cancel>,
+    ) -> DPQAResult {
+        let max_return_stages = match self.final_layout {
+            FinalLayout::Any => 0,
+            _ => self.max_return_stages,
+        };
+        let deadline = self.timeout.map(|t| Instant::now() + t);
+        for n_return_stages in 0..=max_return_stages {
+            if cancel.is_some_and(|c| c.is_cancelled()) {
+                return Err(DPQAError::Timeout);
+            }
+            let n_stages = circuit.get_n_stages() + extra_stages;
+            self.notify(SolveEvent::AttemptStarted {
+                n_stages,
+                n_return_stages,
+            });
+            let result = self.solve_with_stages(
+                circuit,
+                n_stages,
+                n_return_stages,
+                deadline,
+                warm_start,
+                cancel,
+            );
+            if let Err(e) = &result {
+                self.notify(SolveEvent::AttemptFailed(e.clone()));
+            }
+            match result {
+                Err(DPQAError::Unsatisfiable) if n_return_stages < max_return_stages => {}
+                result => return result,
+            }
+        }
+        unreachable!()
+    }
+
+    #[cfg(feature = "z3")]
+    /// Create a context for one solver run, with the time left until the
+    /// deadline as its timeout. Each thread needs its own context.
+    fn new_context(deadline: Option<Instant>) -> Result<Context, DPQAError> {
+        let mut cfg = Config::new();
+        if let Some(deadline) = deadline {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(DPQAError::Timeout);
+            }
+            cfg.set_timeout_msec(remaining.as_millis().max(1) as u64);
+        }
+        Ok(Context::new(&cfg))
+    }
+
+    #[cfg(feature = "z3")]
+    /// Attempt to solve with the given number of stages for gates, followed
+    /// by the given number of stages (without gates) to reach the final
+    /// layout
+    fn solve_with_stages(
+        &self,
+        circuit: &Circuit,
+        n_stages: usize,
+        n_return_stages: usize,
+        deadline: Option<Instant>,
+        warm_start: Option<&WarmStart>,
+        cancel: Option<&Cancel>,
+    ) -> DPQAResult {
+        let ctx = DPQA::new_context(deadline)?;
+        let mut solver = LexOptimize::new(&ctx);
+        if let Some(seed) = self.random_seed {
+            solver.set_random_seed(seed);
+        }
+        if let Some(deadline) = deadline {
+            solver.set_deadline(deadline);
+        }
+        if let Some(cancel) = cancel {
+            solver.set_stop(|| cancel.is_cancelled());
+        }
+
+        let vars = DPQAVars::new(&ctx, circuit, self, n_stages, n_return_stages);
+        self.notify(SolveEvent::VariablesBuilt {
+            n_qubits: circuit.get_n_qubits(),
+            n_gates: circuit.get_n_two_qubit_gates(),
+        });
+        vars.set_constraints(&solver, &mut |family, n_assertions| {
+            self.notify(SolveEvent::ConstraintsAdded {
+                family,
+                n_assertions,
+            })
+        });
+        if self.symmetry_breaking && warm_start.is_none() {
+            self.add_counted(&solver, "symmetry_breaking", |s| {
+                vars.constraint_symmetry_breaking(s)
+            });
+        }
+        vars.set_optimization(&solver);
+        let mut kept = vec![];
+        if let Some(warm_start) = warm_start {
+            if let Some(loaded) = &warm_start.loaded {
+                self.add_counted(&solver, "loaded_atoms", |s| {
+                    vars.constraint_loaded_atoms(s, loaded)
+                });
+            }
+            // Keeping the hint only breaks ties between optimal schedules
+            kept = self.add_counted(&solver, "keep_trajectories", |s| {
+                vars.keep_trajectories(s, warm_start.previous, &warm_start.keep)
+            });
+            if warm_start.keep_gate_times {
+                kept.extend(self.add_counted(&solver, "keep_gate_times", |s| {
+                    vars.keep_gate_times(s, warm_start.previous)
+                }));
+            }
+        }
+
+        self.notify(SolveEvent::CheckStarted);
+        let start = Instant::now();
+        let found = |objectives: &[i64]| {
+            self.notify(SolveEvent::ModelFound {
+                elapsed: start.elapsed(),
+                objectives: objectives.to_vec(),
+            })
+        };
+        // Check whether the hint works as it is, then optimize without it
+        let check = || {
+            if kept.is_empty() {
+                let status = solver.check(&[], &found);
+                return (status, status);
+            }
+            match solver.check_assumptions(&kept) {
+                SatResult::Unknown => (SatResult::Unknown, SatResult::Unknown),
+                first_status => (first_status, solver.check(&[], &found)),
+            }
+        };
+        let (first_status, status) = match cancel {
+            Some(cancel) => cancel.run(&ctx, check),
+            None => check(),
+        };
+        let hint_feasible = warm_start.map(|_| first_status == SatResult::Sat);
+        let model = match status {
+            SatResult::Sat => solver.take_model(),
+            SatResult::Unsat => return Err(DPQAError::Unsatisfiable),
+            SatResult::Unknown => {
+                // Z3 does not always report a timeout or an interruption as
+                // the reason, so also check the elapsed time and the signal
+                let reason = solver.get_reason_unknown().unwrap_or_default();
+                let timed_out = deadline.is_some_and(|d| Instant::now() >= d)
+                    || cancel.is_some_and(|c| c.is_cancelled());
+                if !(timed_out || reason.contains("timeout") || reason.contains("canceled")) {
+                    return Err(DPQAError::SolverUnknown(reason));
                 }
+                // Return the best schedule found before stopping, if any
+                Some(solver.take_model().ok_or(DPQAError::Timeout)?)
+            }
+        };
+
+        let model =
+            model.ok_or_else(|| DPQAError::ModelExtraction(String::from("no model available")))?;
+        let vals = vars.eval(&model)?;
+        let schedule = Schedule::from_values(circuit, &vals, &self.addressing)?;
+        let mut solution = Solution::with_return_stages(schedule, n_return_stages);
+        solution.set_hint_feasible(hint_feasible);
+        solution.set_optimal(status == SatResult::Sat);
+        solution = self.labelled(solution);
+        self.notify(SolveEvent::SolutionExtracted {
+            n_stages: solution.get_n_stages(),
+            n_transfers: solution.get_n_transfers(),
+        });
+        Ok(solution)
+    }
+
+    #[cfg(feature = "z3")]
+    /// Add a family of constraints to the solver, and report the number of
+    /// assertions added
+    fn add_counted<'ctx, S: ConstraintSink<'ctx>, T>(
+        &self,
+        solver: &S,
+        family: &'static str,
+        add: impl FnOnce(&CountingSink<S>) -> T,
+    ) -> T {
+        let counted = CountingSink::new(solver);
+        let result = add(&counted);
+        self.notify(SolveEvent::ConstraintsAdded {
+            family,
+            n_assertions: counted.n_assertions(),
+        });
+        result
+    }
+
+    /// Set the qubit map of the solution, if one was given
+    pub(crate) fn labelled(&self, mut solution: Solution) -> Solution {
+        if let Some(map) = &self.qubit_map {
+            solution.set_qubit_map(map.clone());
+        }
+        solution
+    }
+
+    #[cfg(feature = "z3")]
+    /// Report progress to the observer and the log
+    fn notify(&self, event: SolveEvent) {
+        #[cfg(feature = "log")]
+        log::log!(event.level(), "{}", event);
+        if let Some(observer) = &self.observer {
+            observer(&event);
+        }
+    }
+
+    /// Run cheap checks that rule out compiling the circuit on this grid,
+    /// without building any solver constraints.
+    pub fn check_feasible(&self, circuit: &Circuit) -> Result<(), DPQAError> {
+        if self.rows == 0 || self.cols == 0 {
+            return Err(DPQAError::InvalidArchitecture(format!(
+                "grid must have at least one site, got {} x {}",
+                self.rows, self.cols
+            )));
+        }
+        if self.aod_rows == 0 || self.aod_cols == 0 {
+            return Err(DPQAError::InvalidArchitecture(format!(
+                "AOD grid must have at least one row and column, got {} x {}",
+                self.aod_rows, self.aod_cols
+            )));
+        }
+        if self.interaction_radius == 0 {
+            return Err(DPQAError::InvalidArchitecture(String::from(
+                "interaction radius must be at least 1",
+            )));
+        }
+        let addressable_in_grid = match &self.addressing {
+            Addressing::Global => true,
+            Addressing::Sites(sites) => sites.iter().any(|&(x, y)| x < self.cols && y < self.rows),
+            Addressing::Rows(rows) => rows.iter().any(|&y| y < self.rows),
+            Addressing::Cols(cols) => cols.iter().any(|&x| x < self.cols),
+        };
+        if !addressable_in_grid {
+            return Err(DPQAError::InvalidArchitecture(String::from(
+                "no addressable sites within the grid",
+            )));
+        }
+        if let Some(zone) = &self.readout_zone {
+            if zone.is_empty() || zone.iter().any(|&y| y >= self.rows) {
+                return Err(DPQAError::InvalidArchitecture(format!(
+                    "readout zone rows {:?} must be within the grid",
+                    zone
+                )));
+            }
+        }
+
+        if circuit.get_n_two_qubit_gates() == 0 {
+            return Err(DPQAError::EmptyCircuit);
+        }
+
+        if self.cyclic && self.final_layout != FinalLayout::Any {
+            return Err(DPQAError::InvalidArchitecture(String::from(
+                "cyclic schedules cannot have a final layout",
+            )));
+        }
+        if let FinalLayout::Custom(sites) = &self.final_layout {
+            if sites.len() != circuit.get_n_qubits() {
+                return Err(DPQAError::InvalidArchitecture(format!(
+                    "final layout has {} sites for {} qubits",
+                    sites.len(),
+                    circuit.get_n_qubits()
+                )));
+            }
+            if sites.iter().any(|&(x, y)| x >= self.cols || y >= self.rows)
+                || sites.iter().sorted().dedup().count() != sites.len()
+            {
+                return Err(DPQAError::InvalidArchitecture(format!(
+                    "final layout sites {:?} must be distinct and within the grid",
+                    sites
+                )));
+            }
+        }
+
+        if let Some(map) = &self.qubit_map {
+            if map.get_n_qubits() != circuit.get_n_qubits() {
+                return Err(DPQAError::InvalidArchitecture(format!(
+                    "qubit map has {} qubits for {} qubits in the circuit",
+                    map.get_n_qubits(),
+                    circuit.get_n_qubits()
+                )));
             }
-            return DPQAResult::Succeeded(instructions);
         }
-        DPQAResult::Failed
+
+        // Each site holds at most one SLM atom, and each AOD row/column
+        // intersection holds at most one AOD atom
+        let sites = self.rows * self.cols;
+        let capacity = sites + sites.min(self.aod_rows * self.aod_cols);
+        let n_qubits = circuit.get_n_qubits();
+        if n_qubits as u64 > capacity {
+            return Err(DPQAError::GridTooSmall { n_qubits, capacity });
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "z3")]
+    /// Lower bound on the number of sites needed to run the gates. With a
+    /// global pulse, all co-located atoms interact, so while the gates of one
+    /// type run, every qubit not in one of these gates needs its own site.
+    fn min_sites_separated(&self, circuit: &Circuit) -> u64 {
+        if self.addressing != Addressing::Global {
+            return 0;
+        }
+        let n_qubits = circuit.get_n_qubits();
+        circuit
+            .iter()
+            .into_group_map_by(|g| g.gate_type)
+            .into_values()
+            .map(|gates| {
+                // Gates of a stage act on distinct qubits
+                let n_gate_qubits = gates
+                    .iter()
+                    .flat_map(|g| [g.q_ctrl, g.q_target])
+                    .unique()
+                    .count();
+                let max_parallel = gates.len().min(n_gate_qubits / 2);
+                (n_qubits - max_parallel) as u64
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Get the number of rows of SLM traps
+    pub fn get_rows(&self) -> u64 {
+        self.rows
+    }
+
+    /// Get the number of columns of SLM traps
+    pub fn get_cols(&self) -> u64 {
+        self.cols
+    }
+
+    /// Get the number of AOD rows
+    pub fn get_aod_rows(&self) -> u64 {
+        self.aod_rows
+    }
+
+    /// Get the number of AOD columns
+    pub fn get_aod_cols(&self) -> u64 {
+        self.aod_cols
+    }
+
+    /// Get the interaction radius, in site units
+    pub fn get_interaction_radius(&self) -> u64 {
+        self.interaction_radius
+    }
+
+    /// Set the interaction radius: atoms closer than this distance (in site
+    /// units, taking the larger of the x and y separations) interact when the
+    /// Rydberg laser fires. At stages where gates are executed, atoms that
+    /// are not part of the same gate are kept at least this far apart. The
+    /// default of 1 only keeps them off the same site.
+    pub fn set_interaction_radius(&mut self, radius: u64) {
+        self.interaction_radius = radius;
+    }
+
+    /// Get the sites that can be illuminated by the Rydberg laser
+    pub fn get_addressing(&self) -> &Addressing {
+        &self.addressing
+    }
+
+    /// Set the sites that can be illuminated by the Rydberg laser. With
+    /// locally addressed beams, gates are only executed at covered sites, and
+    /// atoms sharing a site do not interact unless one of them is part of a
+    /// gate. The default is `Addressing::Global`.
+    pub fn set_addressing(&mut self, addressing: Addressing) {
+        self.addressing = addressing;
+    }
+
+    /// Get the rows of the readout zone, if one is set
+    pub fn get_readout_zone(&self) -> Option<&[u64]> {
+        self.readout_zone.as_deref()
+    }
+
+    /// Set the rows of the readout zone. When a qubit is measured, it must
+    /// be in one of these rows, and all other atoms must be outside them
+    /// (shielded from the imaging light). By default there is no readout
+    /// zone, and atoms are measured in place.
+    pub fn set_readout_zone(&mut self, rows: Vec<u64>) {
+        self.readout_zone = Some(rows);
+    }
+
+    /// Check whether schedules are compiled to be repeated
+    pub fn is_cyclic(&self) -> bool {
+        self.cyclic
+    }
+
+    /// Compile schedules that can be repeated indefinitely, e.g. for rounds
+    /// of error correction. The schedule gets one extra stage without gates,
+    /// which returns every atom to its initial position and trap; see
+    /// `Schedule::unroll`.
+    pub fn set_cyclic(&mut self, cyclic: bool) {
+        self.cyclic = cyclic;
+    }
+
+    /// Get the configuration required at the end of the schedule
+    pub fn get_final_layout(&self) -> &FinalLayout {
+        &self.final_layout
+    }
+
+    /// Require a configuration at the end of the schedule, e.g. to return
+    /// atoms to their loading positions for the next shot. Stages without
+    /// gates are added at the end as needed to reach it (see
+    /// `set_max_return_stages`), and their cost is reported by
+    /// `Solution::return_trip`. The default is `FinalLayout::Any`.
+    pub fn set_final_layout(&mut self, layout: FinalLayout) {
+        self.final_layout = layout;
+    }
+
+    /// Set the maximum number of stages that can be added to reach the final
+    /// layout (3 by default)
+    pub fn set_max_return_stages(&mut self, max_return_stages: usize) {
+        self.max_return_stages = max_return_stages;
+    }
+
+    /// Get the encoding of the solver variables
+    pub fn get_encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// Set how positions, AOD indices and stages are encoded in the solver.
+    /// The schedules found are equally good, but the solving time can differ
+    /// a lot: the bounded encodings avoid arithmetic, and often help on
+    /// larger grids (see `benches/encoding.rs`). The default is
+    /// `Encoding::Int`.
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        self.encoding = encoding;
+    }
+
+    /// Check whether symmetry-breaking constraints are added
+    pub fn get_symmetry_breaking(&self) -> bool {
+        self.symmetry_breaking
+    }
+
+    /// Add constraints that rule out mirrored grids, relabelled idle qubits
+    /// and reindexed AOD lines. They do not change which circuits fit or how
+    /// good the schedule is, but can make proving that a circuit does not fit
+    /// much faster. They are not used by `solve_with_hint` and
+    /// `recompile_after_loss`. Off by default.
+    pub fn set_symmetry_breaking(&mut self, symmetry_breaking: bool) {
+        self.symmetry_breaking = symmetry_breaking;
+    }
+
+    /// Get the number of stages added beyond the minimum
+    pub fn get_extra_stages(&self) -> usize {
+        self.extra_stages
     }
 
     /// Increase the number of stages (time steps) beyond the minimum number
@@ -175,6 +929,70 @@ impl DPQA {
     pub fn set_extra_stages(&mut self, extra_stages: usize) {
         self.extra_stages = extra_stages;
     }
+
+    /// Call the given function with the progress of each solve (see
+    /// `progress::SolveEvent`). The function may be called from several
+    /// threads, e.g. by a `portfolio::Portfolio`.
+    /// ```
+    /// use dpqa_rs::dpqa::DPQA;
+    /// use dpqa_rs::progress::SolveEvent;
+    ///
+    /// let mut dpqa = DPQA::new(2, 2);
+    /// dpqa.set_observer(|event: &SolveEvent| eprintln!("{}", event));
+    /// ```
+    pub fn set_observer(&mut self, observer: impl Fn(&SolveEvent) + Send + Sync + 'static) {
+        self.observer = Some(Arc::new(observer));
+    }
+
+    /// Get the map used to restore the original qubit labels, if any
+    pub fn get_qubit_map(&self) -> Option<&QubitMap> {
+        self.qubit_map.as_ref()
+    }
+
+    /// Label the qubits in the instructions of each solution with their
+    /// indices before the circuit was renumbered, as returned by
+    /// `Circuit::renumber_qubits`. The map must have as many qubits as the
+    /// compiled circuits (see `check_feasible`). Solutions still verify
+    /// against the renumbered circuit, and only `Solution::instructions` is
+    /// translated: `Solution::schedule` and the renderers keep the
+    /// renumbered indices.
+    /// ```
+    /// use dpqa_rs::circuit::Circuit;
+    /// use dpqa_rs::compiler::Compiler;
+    /// use dpqa_rs::dpqa::DPQA;
+    /// use dpqa_rs::gates::{TwoQubitGate, TwoQubitGateType::CZ};
+    /// use dpqa_rs::greedy::GreedyCompiler;
+    ///
+    /// let mut circuit = Circuit::new();
+    /// circuit.append(TwoQubitGate::new(CZ, 3, 7));
+    /// circuit.set_qubit_name(7, "anc[1]");
+    ///
+    /// let mut dpqa = DPQA::new(2, 2);
+    /// dpqa.set_qubit_map(circuit.renumber_qubits());
+    /// let solution = GreedyCompiler::new(&dpqa).compile(&circuit).unwrap();
+    /// let map = solution.qubit_map().unwrap();
+    /// for x in solution.instructions() {
+    ///     println!("{}", x.display_with(|q| map.label(q)));
+    /// }
+    /// assert_eq!(solution.verify(&circuit), Ok(()));
+    /// ```
+    pub fn set_qubit_map(&mut self, map: QubitMap) {
+        self.qubit_map = Some(map);
+    }
+
+    /// Stop the solver after the given time. The best schedule found by
+    /// then is returned, not proven optimal (see `Solution::is_optimal`), or
+    /// `DPQAError::Timeout` if there is none.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = Some(timeout);
+    }
+
+    /// Fix the random seed used by Z3. The seed is a parameter of each
+    /// solver, so configurations with different seeds can be solved at the
+    /// same time (see `portfolio::Portfolio`).
+    pub fn set_random_seed(&mut self, seed: u32) {
+        self.random_seed = Some(seed);
+    }
 }
 
 impl fmt::Display for DPQA {
@@ -187,12 +1005,33 @@ impl fmt::Display for DPQA {
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "z3"))]
 mod tests {
-    use super::{DPQAResult, DPQA};
+    use super::{Addressing, Encoding, FinalLayout, GridLimits, DPQA};
     use crate::circuit::Circuit;
-    use crate::gates::TwoQubitGate;
+    use crate::compiler::Compiler;
+    use crate::error::DPQAError;
     use crate::gates::TwoQubitGateType::{CX, CZ};
+    use crate::gates::{QubitOp, TwoQubitGate};
+    use crate::greedy::GreedyCompiler;
+    use crate::instruction::DPQAInstruction;
+    use crate::portfolio::Cancel;
+    use crate::progress::SolveEvent;
+    use crate::schedule::{AtomState, DPQAVarsValues, Schedule};
+    use crate::simulate::{verify, Tableau};
+    use std::sync::{Arc, Mutex};
+
+    fn count_transfers(instructions: &[DPQAInstruction]) -> usize {
+        instructions
+            .iter()
+            .filter(|i| {
+                matches!(
+                    i,
+                    DPQAInstruction::MoveToSLM(_) | DPQAInstruction::MoveToAOD { .. }
+                )
+            })
+            .count()
+    }
 
     #[test]
     fn one_gate() {
@@ -200,7 +1039,31 @@ mod tests {
         circuit.append(TwoQubitGate::new(CZ, 0, 1));
 
         let dpqa = DPQA::new(2, 1);
-        assert!(matches!(dpqa.solve(&circuit), DPQAResult::Succeeded(_)));
+        assert!(dpqa.solve(&circuit).is_ok());
+    }
+
+    #[test]
+    /// Transfers are minimized even if there is a single gate, when extra
+    /// stages are added
+    fn one_gate_extra_stages() {
+        let mut circuit = Circuit::new();
+        circuit.append(TwoQubitGate::new(CZ, 0, 1));
+
+        let mut dpqa = DPQA::new(2, 1);
+        dpqa.set_extra_stages(2);
+        let solution = dpqa.solve(&circuit).unwrap();
+        assert_eq!(solution.get_n_stages(), 3);
+        let n_transfers = solution
+            .instructions()
+            .iter()
+            .filter(|i| {
+                matches!(
+                    i,
+                    DPQAInstruction::MoveToSLM(_) | DPQAInstruction::MoveToAOD { .. }
+                )
+            })
+            .count();
+        assert_eq!(n_transfers, 0);
     }
 
     #[test]
@@ -213,12 +1076,8 @@ mod tests {
         let dpqa = DPQA::new(2, 1);
         let result = dpqa.solve(&circuit);
 
-        if let DPQAResult::Succeeded(instructions) = result {
-            for x in &instructions {
-                println!("{}", x);
-            }
-        } else {
-            assert!(false)
+        for x in result.unwrap().instructions() {
+            println!("{}", x);
         }
     }
 
@@ -238,12 +1097,8 @@ mod tests {
         let dpqa = DPQA::new_aod(3, 2, 1, 2);
         let result = dpqa.solve(&circuit);
 
-        if let DPQAResult::Succeeded(instructions) = result {
-            for x in &instructions {
-                println!("{}", x);
-            }
-        } else {
-            assert!(false)
+        for x in result.unwrap().instructions() {
+            println!("{}", x);
         }
     }
 
@@ -265,20 +1120,17 @@ mod tests {
         for p in qubit_pairs {
             circuit.append(TwoQubitGate::new(CZ, p.0, p.1));
         }
-        assert!(!circuit.renumber_qubits());
+        assert!(circuit.renumber_qubits().is_identity());
         assert!(circuit.recalculate_stages());
         assert_eq!(circuit.get_n_stages(), 4);
 
         let dpqa = DPQA::new(2, 4);
-        let result = dpqa.solve(&circuit);
+        let result = dpqa.solve(&circuit).unwrap();
 
-        if let DPQAResult::Succeeded(instructions) = result {
-            for x in &instructions {
-                println!("{}", x);
-            }
-        } else {
-            assert!(false)
+        for x in result.instructions() {
+            println!("{}", x);
         }
+        assert_eq!(result.verify(&circuit), Ok(()));
     }
 
     #[test]
@@ -297,18 +1149,644 @@ mod tests {
         {
             let dpqa_too_small = DPQA::new(2, 2);
             let failed = dpqa_too_small.solve(&circuit);
-            assert!(failed == DPQAResult::Failed);
+            assert_eq!(failed, Err(DPQAError::Unsatisfiable));
         }
 
         let dpqa = DPQA::new(2, 3);
         let result = dpqa.solve(&circuit);
 
-        if let DPQAResult::Succeeded(instructions) = result {
-            for x in &instructions {
-                println!("{}", x);
+        for x in result.unwrap().instructions() {
+            println!("{}", x);
+        }
+    }
+
+    #[test]
+    fn observer() {
+        let mut circuit = Circuit::new();
+        circuit.append(TwoQubitGate::new(CZ, 0, 1));
+        circuit.append(TwoQubitGate::new(CZ, 1, 2));
+        circuit.append(TwoQubitGate::new(CZ, 0, 2));
+
+        let events = Arc::new(Mutex::new(vec![]));
+        let mut dpqa = DPQA::new(2, 2);
+        let observed = Arc::clone(&events);
+        dpqa.set_observer(move |event: &SolveEvent| observed.lock().unwrap().push(event.clone()));
+        let solution = dpqa.solve(&circuit).unwrap();
+
+        let events = std::mem::take(&mut *events.lock().unwrap());
+        assert_eq!(
+            events[..2],
+            [
+                SolveEvent::AttemptStarted {
+                    n_stages: 3,
+                    n_return_stages: 0
+                },
+                SolveEvent::VariablesBuilt {
+                    n_qubits: 3,
+                    n_gates: 3
+                }
+            ]
+        );
+        let families: Vec<_> = events
+            .iter()
+            .filter_map(|e| match e {
+                SolveEvent::ConstraintsAdded {
+                    family,
+                    n_assertions,
+                } => Some((*family, *n_assertions)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(families.len(), 18);
+        assert!(families[0].0 == "grid_bounds" && families[0].1 > 0);
+        assert!(families.contains(&("cyclic", 0)));
+        // Every model found improves on the previous one, and the last one
+        // has the fewest transfers
+        let check = events
+            .iter()
+            .position(|e| *e == SolveEvent::CheckStarted)
+            .unwrap();
+        let objectives: Vec<_> = events[check + 1..events.len() - 1]
+            .iter()
+            .map(|e| match e {
+                SolveEvent::ModelFound { objectives, .. } => objectives.clone(),
+                e => panic!("unexpected event {}", e),
+            })
+            .collect();
+        assert!(!objectives.is_empty());
+        assert!(objectives.windows(2).all(|w| w[1] < w[0]));
+        assert_eq!(
+            objectives.last().unwrap()[0],
+            solution.get_n_transfers() as i64
+        );
+        assert_eq!(
+            events[events.len() - 1],
+            SolveEvent::SolutionExtracted {
+                n_stages: 3,
+                n_transfers: solution.get_n_transfers()
+            }
+        );
+    }
+
+    #[test]
+    /// Stopping the solver after a model was found returns that model
+    fn interrupted() {
+        let mut circuit = Circuit::new();
+        for (q0, q1) in [(2, 4), (3, 5), (0, 1), (2, 3), (4, 5), (0, 2), (1, 3)] {
+            circuit.append(TwoQubitGate::new(CZ, q0, q1));
+        }
+        circuit.recalculate_stages();
+        let mut dpqa = DPQA::new(2, 4);
+        assert!(dpqa.solve(&circuit).unwrap().is_optimal());
+
+        let cancel = Arc::new(Cancel::default());
+        let signal = Arc::clone(&cancel);
+        dpqa.set_observer(move |event: &SolveEvent| {
+            if matches!(event, SolveEvent::ModelFound { .. }) {
+                signal.cancel();
+            }
+        });
+        let solution = dpqa.solve_cancellable(&circuit, &cancel).unwrap();
+        assert!(!solution.is_optimal());
+        assert_eq!(solution.verify(&circuit), Ok(()));
+    }
+
+    #[test]
+    fn qubit_map() {
+        let mut circuit = Circuit::new();
+        circuit.append(TwoQubitGate::new(CZ, 3, 7));
+        circuit.append(TwoQubitGate::new(CZ, 7, 9));
+        let mut dpqa = DPQA::new(2, 2);
+        dpqa.set_qubit_map(circuit.renumber_qubits());
+        let solution = dpqa.solve(&circuit).unwrap();
+
+        let mut initialized: Vec<usize> = solution
+            .instructions()
+            .iter()
+            .filter_map(|x| match x {
+                DPQAInstruction::Init { qubit, .. } => Some(*qubit),
+                _ => None,
+            })
+            .collect();
+        initialized.sort();
+        assert_eq!(initialized, [3, 7, 9]);
+        assert_eq!(solution.verify(&circuit), Ok(()));
+
+        // The map does not fit a circuit with more qubits
+        circuit.append(TwoQubitGate::new(CZ, 2, 3));
+        assert!(matches!(
+            dpqa.solve(&circuit),
+            Err(DPQAError::InvalidArchitecture(_))
+        ));
+    }
+
+    #[test]
+    fn min_grid() {
+        let mut circuit = Circuit::new();
+        circuit.append(TwoQubitGate::new(CZ, 0, 2));
+        circuit.append(TwoQubitGate::new(CZ, 1, 3));
+        circuit.append(TwoQubitGate::new(CX, 4, 5));
+        circuit.append(TwoQubitGate::new(CX, 6, 7));
+        circuit.recalculate_stages();
+
+        // 2 x 2 holds 8 atoms, but qubits of the other gate type need 6 sites
+        let dpqa = DPQA::new(1, 1);
+        assert_eq!(dpqa.min_sites_separated(&circuit), 6);
+        let mut limits = GridLimits::new(6, 6);
+        limits.max_aspect_ratio = Some(2);
+        let (found, solution) = dpqa.find_min_grid(&circuit, &limits).unwrap();
+        assert_eq!((found.get_rows(), found.get_cols()), (2, 3));
+        assert_eq!((found.get_aod_rows(), found.get_aod_cols()), (2, 3));
+        assert_eq!(solution.verify(&circuit), Ok(()));
+
+        let mut chain = Circuit::new();
+        chain.append(TwoQubitGate::new(CZ, 0, 1));
+        chain.append(TwoQubitGate::new(CZ, 1, 2));
+        let mut limits = GridLimits::new(2, 2);
+        limits.search_aod = true;
+        let (found, solution) = dpqa.find_min_grid(&chain, &limits).unwrap();
+        assert_eq!(found.get_rows() * found.get_cols(), 2);
+        assert_eq!(found.get_aod_rows() * found.get_aod_cols(), 1);
+        assert_eq!(solution.verify(&chain), Ok(()));
+
+        assert!(matches!(
+            dpqa.find_min_grid(&circuit, &GridLimits::new(1, 3)),
+            Err(DPQAError::GridTooSmall { .. })
+        ));
+        assert!(matches!(
+            dpqa.find_min_grid(&circuit, &GridLimits::new(0, 3)),
+            Err(DPQAError::InvalidArchitecture(_))
+        ));
+    }
+
+    #[test]
+    /// Gate indices that are not sorted by stage must still be reported
+    fn gates_out_of_order() {
+        let mut circuit = Circuit::new();
+        circuit.append(TwoQubitGate::new(CZ, 0, 1));
+        circuit.append(TwoQubitGate::new(CZ, 1, 2));
+        circuit.append(TwoQubitGate::new(CZ, 3, 4));
+        circuit.recalculate_stages();
+
+        let dpqa = DPQA::new(2, 3);
+        let solution = dpqa.solve(&circuit).unwrap();
+        let n_gates: usize = solution
+            .instructions()
+            .iter()
+            .map(|x| match x {
+                DPQAInstruction::Gate { gates, .. } => gates.len(),
+                _ => 0,
+            })
+            .sum();
+        assert_eq!(n_gates, 3);
+    }
+
+    #[test]
+    fn pre_checks() {
+        let mut circuit = Circuit::new();
+        assert_eq!(
+            DPQA::new(2, 2).solve(&circuit),
+            Err(DPQAError::EmptyCircuit)
+        );
+
+        circuit.append(TwoQubitGate::new(CZ, 0, 1));
+        circuit.append(TwoQubitGate::new(CZ, 2, 3));
+        assert!(matches!(
+            DPQA::new(0, 2).solve(&circuit),
+            Err(DPQAError::InvalidArchitecture(_))
+        ));
+        assert!(matches!(
+            DPQA::new_aod(2, 2, 0, 1).solve(&circuit),
+            Err(DPQAError::InvalidArchitecture(_))
+        ));
+        assert_eq!(
+            DPQA::new_aod(1, 1, 1, 1).solve(&circuit),
+            Err(DPQAError::GridTooSmall {
+                n_qubits: 4,
+                capacity: 2
+            })
+        );
+    }
+
+    #[test]
+    /// Identical inputs must give identical instructions
+    fn deterministic_output() {
+        let mut circuit = Circuit::new();
+        circuit.append(TwoQubitGate::new(CZ, 0, 2));
+        circuit.append(TwoQubitGate::new(CZ, 1, 3));
+        circuit.append(TwoQubitGate::new(CZ, 0, 4));
+        circuit.append(TwoQubitGate::new(CZ, 1, 5));
+        circuit.recalculate_stages();
+
+        let mut dpqa = DPQA::new(2, 3);
+        dpqa.set_random_seed(1);
+        let first = dpqa.solve(&circuit).unwrap();
+        for _ in 0..3 {
+            assert_eq!(dpqa.solve(&circuit).unwrap(), first);
+        }
+    }
+
+    #[test]
+    fn interaction_radius() {
+        let mut circuit = Circuit::new();
+        circuit.append(TwoQubitGate::new(CZ, 0, 1));
+        circuit.append(TwoQubitGate::new(CZ, 2, 3));
+        circuit.recalculate_stages();
+
+        // The two gates can run side by side...
+        let mut dpqa = DPQA::new(1, 2);
+        let result = dpqa.solve(&circuit).unwrap();
+        assert_eq!(result.get_n_stages(), 1);
+
+        // ...but not if neighboring sites interact
+        dpqa.set_interaction_radius(2);
+        assert_eq!(dpqa.solve(&circuit), Err(DPQAError::Unsatisfiable));
+
+        let mut dpqa = DPQA::new(1, 3);
+        dpqa.set_interaction_radius(2);
+        let result = dpqa.solve(&circuit).unwrap();
+        let closest = result.schedule().min_spectator_distance().unwrap();
+        assert!(closest.distance >= 2);
+
+        dpqa.set_interaction_radius(0);
+        assert!(matches!(
+            dpqa.solve(&circuit),
+            Err(DPQAError::InvalidArchitecture(_))
+        ));
+    }
+
+    #[test]
+    fn local_addressing() {
+        // With two stages, each pair of qubits has to share a site while the
+        // other pair's gate is executed
+        let mut circuit = Circuit::new();
+        circuit.append(TwoQubitGate::new(CZ, 0, 1));
+        circuit.append(TwoQubitGate::new(CZ, 2, 3));
+
+        let mut dpqa = DPQA::new(1, 2);
+        assert_eq!(dpqa.solve(&circuit), Err(DPQAError::Unsatisfiable));
+
+        dpqa.set_addressing(Addressing::Cols(vec![0, 1]));
+        let result = dpqa.solve(&circuit).unwrap();
+        assert_eq!(result.get_n_stages(), 2);
+        assert_eq!(result.verify(&circuit), Ok(()));
+        for stage in result.schedule().iter() {
+            let g = stage.gates[0];
+            let a = stage.atoms[g.q_ctrl];
+            assert_eq!(stage.illuminated, Some(vec![(a.x, a.y)]));
+        }
+
+        dpqa.set_addressing(Addressing::Rows(vec![1]));
+        assert!(matches!(
+            dpqa.solve(&circuit),
+            Err(DPQAError::InvalidArchitecture(_))
+        ));
+    }
+
+    #[test]
+    fn measure_and_reset() {
+        let mut circuit = Circuit::new();
+        circuit.append(TwoQubitGate::new(CZ, 0, 1));
+        circuit.measure(1, 0);
+        circuit.reset(1);
+        circuit.append(TwoQubitGate::new(CZ, 1, 2));
+        circuit.measure(1, 1);
+        circuit.recalculate_stages();
+
+        let mut dpqa = DPQA::new(2, 2);
+        let result = dpqa.solve(&circuit).unwrap();
+        let instructions = result.instructions();
+        let position = |inst: &DPQAInstruction| instructions.iter().position(|i| i == inst);
+        let gate_positions: Vec<usize> = instructions
+            .iter()
+            .enumerate()
+            .filter(|(_, i)| matches!(i, DPQAInstruction::Gate { .. }))
+            .map(|(ii, _)| ii)
+            .collect();
+        let measure_0 = position(&DPQAInstruction::Measure { qubit: 1, bit: 0 }).unwrap();
+        let reset = position(&DPQAInstruction::Reset(1)).unwrap();
+        let measure_1 = position(&DPQAInstruction::Measure { qubit: 1, bit: 1 }).unwrap();
+        assert!(gate_positions[0] < measure_0);
+        assert!(measure_0 < reset);
+        assert!(reset < gate_positions[1]);
+        assert!(gate_positions[1] < measure_1);
+
+        // Measured atoms must be moved into the bottom row, away from the
+        // other atoms
+        dpqa.set_readout_zone(vec![1]);
+        dpqa.set_extra_stages(2);
+        let result = dpqa.solve(&circuit).unwrap();
+        assert_eq!(result.verify(&circuit), Ok(()));
+        for stage in result.schedule().iter() {
+            for op in &stage.ops {
+                if let QubitOp::Measure { qubit, .. } = op {
+                    for (ii, a) in stage.atoms.iter().enumerate() {
+                        assert_eq!(a.y == 1, ii == *qubit);
+                    }
+                }
+            }
+        }
+
+        dpqa.set_readout_zone(vec![2]);
+        assert!(matches!(
+            dpqa.solve(&circuit),
+            Err(DPQAError::InvalidArchitecture(_))
+        ));
+    }
+
+    #[test]
+    fn cyclic() {
+        let mut circuit = Circuit::new();
+        circuit.append(TwoQubitGate::new(CZ, 0, 1));
+        circuit.append(TwoQubitGate::new(CZ, 1, 2));
+        circuit.append(TwoQubitGate::new(CZ, 0, 2));
+
+        let mut dpqa = DPQA::new(2, 2);
+        dpqa.set_cyclic(true);
+        let result = dpqa.solve(&circuit).unwrap();
+        let schedule = result.schedule();
+        assert_eq!(schedule.get_n_stages(), circuit.get_n_stages() + 1);
+        assert!(schedule.is_cyclic());
+
+        let mut repeated = Circuit::new();
+        for _ in 0..3 {
+            circuit.iter().for_each(|g| repeated.append(*g));
+        }
+        let unrolled = schedule.unroll(3).unwrap();
+        assert_eq!(verify::<Tableau>(&repeated, &unrolled), Ok(()));
+        let n_init = unrolled
+            .iter()
+            .filter(|i| matches!(i, DPQAInstruction::Init { .. }))
+            .count();
+        assert_eq!(n_init, 3);
+    }
+
+    #[test]
+    fn final_layout() {
+        let mut circuit = Circuit::new();
+        circuit.append(TwoQubitGate::new(CZ, 0, 1));
+        circuit.append(TwoQubitGate::new(CZ, 1, 2));
+        circuit.append(TwoQubitGate::new(CZ, 0, 2));
+
+        let mut dpqa = DPQA::new(2, 2);
+        dpqa.set_final_layout(FinalLayout::AllSLM);
+        let result = dpqa.solve(&circuit).unwrap();
+        let last = result.schedule().iter().last().unwrap();
+        assert!(last.atoms.iter().all(|a| a.aod.is_none()));
+        assert_eq!(result.verify(&circuit), Ok(()));
+
+        dpqa.set_final_layout(FinalLayout::Initial);
+        let result = dpqa.solve(&circuit).unwrap();
+        let schedule = result.schedule();
+        assert_eq!(
+            schedule.iter().next().unwrap().atoms,
+            schedule.iter().last().unwrap().atoms
+        );
+        let trip = result.return_trip();
+        assert_eq!(
+            schedule.get_n_stages(),
+            circuit.get_n_stages() + trip.stages
+        );
+
+        let sites = vec![(1, 1), (0, 1), (1, 0)];
+        dpqa.set_final_layout(FinalLayout::Custom(sites.clone()));
+        let result = dpqa.solve(&circuit).unwrap();
+        let last = result.schedule().iter().last().unwrap();
+        let final_sites: Vec<(u64, u64)> = last.atoms.iter().map(|a| (a.x, a.y)).collect();
+        assert_eq!(final_sites, sites);
+        assert!(last.atoms.iter().all(|a| a.aod.is_none()));
+
+        dpqa.set_final_layout(FinalLayout::Custom(vec![(0, 0), (0, 0), (1, 1)]));
+        assert!(matches!(
+            dpqa.solve(&circuit),
+            Err(DPQAError::InvalidArchitecture(_))
+        ));
+        dpqa.set_final_layout(FinalLayout::Initial);
+        dpqa.set_cyclic(true);
+        assert!(matches!(
+            dpqa.solve(&circuit),
+            Err(DPQAError::InvalidArchitecture(_))
+        ));
+    }
+
+    #[test]
+    fn atom_loss() {
+        let mut circuit = Circuit::new();
+        circuit.append(TwoQubitGate::new(CZ, 0, 1));
+        circuit.append(TwoQubitGate::new(CZ, 1, 2));
+        circuit.append(TwoQubitGate::new(CZ, 0, 2));
+
+        let dpqa = DPQA::new(3, 3);
+        let previous = dpqa.solve(&circuit).unwrap().into_schedule();
+        let initial = previous.iter().next().unwrap().atoms.clone();
+        let spare = (0..3)
+            .flat_map(|x| (0..3).map(move |y| (x, y)))
+            .find(|&(x, y)| initial.iter().all(|a| (a.x, a.y) != (x, y)))
+            .unwrap();
+
+        let result = dpqa
+            .recompile_after_loss(&circuit, &previous, &[1], &[spare])
+            .unwrap();
+        assert_eq!(result.verify(&circuit), Ok(()));
+        let mut available = vec![
+            initial[0],
+            initial[2],
+            AtomState {
+                x: spare.0,
+                y: spare.1,
+                aod: None,
+            },
+        ];
+        // AOD atoms may be held by different lines
+        for a in &result.schedule().iter().next().unwrap().atoms {
+            let pos = available
+                .iter()
+                .position(|b| (b.x, b.y, b.in_aod()) == (a.x, a.y, a.in_aod()))
+                .unwrap();
+            available.remove(pos);
+        }
+
+        assert_eq!(
+            dpqa.recompile_after_loss(&circuit, &previous, &[0, 1], &[]),
+            Err(DPQAError::GridTooSmall {
+                n_qubits: 3,
+                capacity: 1
+            })
+        );
+    }
+
+    #[test]
+    fn hint() {
+        let mut circuit = Circuit::new();
+        circuit.append(TwoQubitGate::new(CZ, 0, 1));
+        circuit.append(TwoQubitGate::new(CZ, 1, 2));
+        circuit.append(TwoQubitGate::new(CZ, 0, 2));
+
+        let dpqa = DPQA::new(2, 2);
+        let previous = dpqa.solve(&circuit).unwrap();
+        assert_eq!(previous.hint_feasible(), None);
+        let result = dpqa.solve_with_hint(&circuit, previous.schedule()).unwrap();
+        assert_eq!(result.hint_feasible(), Some(true));
+        assert_eq!(result.schedule(), previous.schedule());
+
+        // The first gate cannot be executed with its qubits apart
+        let vals = DPQAVarsValues {
+            xy: vec![vec![(0, 0), (1, 1), (0, 1)]; 3],
+            cr: vec![vec![(0, 0); 3]; 3],
+            aod: vec![vec![false; 3]; 3],
+            t: vec![0, 1, 2],
+            t_ops: vec![],
+        };
+        let hint = Schedule::from_values(&circuit, &vals, &Addressing::Global).unwrap();
+        let result = dpqa.solve_with_hint(&circuit, &hint).unwrap();
+        assert_eq!(result.hint_feasible(), Some(false));
+        assert_eq!(result.verify(&circuit), Ok(()));
+    }
+
+    #[test]
+    fn heuristic_hint() {
+        let mut circuit = Circuit::new();
+        circuit.append(TwoQubitGate::new(CZ, 0, 1));
+        circuit.append(TwoQubitGate::new(CZ, 1, 2));
+        circuit.append(TwoQubitGate::new(CZ, 0, 2));
+
+        let mut dpqa = DPQA::new(2, 2);
+        let hint = GreedyCompiler::new(&dpqa).compile(&circuit).unwrap();
+        // Allow as many stages as the hint uses, so that it is feasible
+        dpqa.set_extra_stages(hint.get_n_stages() - circuit.get_n_stages());
+        let optimal = dpqa.solve(&circuit).unwrap();
+        // The hint guides the search, but does not change the optimum
+        let result = dpqa.solve_with_hint(&circuit, hint.schedule()).unwrap();
+        assert_eq!(result.hint_feasible(), Some(true));
+        assert!(hint.get_n_transfers() > optimal.get_n_transfers());
+        assert_eq!(result.get_n_transfers(), optimal.get_n_transfers());
+        assert!(result.is_optimal());
+    }
+
+    #[test]
+    fn smtlib2() {
+        let mut circuit = Circuit::new();
+        circuit.append(TwoQubitGate::new(CZ, 0, 1));
+        circuit.append(TwoQubitGate::new(CZ, 1, 2));
+        circuit.append(TwoQubitGate::new(CZ, 0, 2));
+
+        let dpqa = DPQA::new(2, 2);
+        let script = dpqa.to_smtlib2(&circuit).unwrap();
+
+        // Run the script with Z3 as an external solver
+        let ctx = z3::Context::new(&z3::Config::new());
+        let solver = z3::Optimize::new(&ctx);
+        solver.from_string(script);
+        assert_eq!(solver.check(&[]), z3::SatResult::Sat);
+        // Z3 prints models as `name -> value`; rewrite them as `define-fun`s
+        let model = solver.get_model().unwrap().to_string();
+        let definitions = model.lines().filter_map(|line| {
+            let (name, value) = line.split_once(" -> ")?;
+            let sort = match value {
+                "true" | "false" => "Bool",
+                _ => "Int",
+            };
+            Some(format!("(define-fun {} () {} {})", name, sort, value))
+        });
+        let definitions: Vec<_> = definitions.collect();
+        let output = format!("sat\n({})", definitions.concat());
+
+        let result = dpqa.from_smtlib2_model(&circuit, &output).unwrap();
+        assert_eq!(result.verify(&circuit), Ok(()));
+        // Missing values are checked like the others
+        let truncated = format!("sat\n({})", definitions[..3].concat());
+        assert_eq!(
+            dpqa.from_smtlib2_model(&circuit, &truncated),
+            Err(DPQAError::ModelExtraction(String::from(
+                "model violates the constraints"
+            )))
+        );
+        assert_eq!(
+            dpqa.from_smtlib2_model(&circuit, "unsat"),
+            Err(DPQAError::Unsatisfiable)
+        );
+    }
+
+    #[test]
+    /// All encodings find schedules with the same number of transfers
+    fn encodings() {
+        let mut circuit = Circuit::new();
+        circuit.append(TwoQubitGate::new(CZ, 0, 2));
+        circuit.append(TwoQubitGate::new(CZ, 1, 3));
+        circuit.append(TwoQubitGate::new(CZ, 0, 4));
+        circuit.append(TwoQubitGate::new(CZ, 1, 5));
+        circuit.recalculate_stages();
+
+        let mut spaced = Circuit::new();
+        spaced.append(TwoQubitGate::new(CZ, 0, 1));
+        spaced.append(TwoQubitGate::new(CZ, 1, 2));
+        spaced.append(TwoQubitGate::new(CX, 2, 3));
+
+        let mut dpqa = DPQA::new_aod(3, 2, 1, 2);
+        let mut dpqa_spaced = DPQA::new(3, 3);
+        dpqa_spaced.set_interaction_radius(2);
+        for (dpqa, circuit) in [(&mut dpqa, &circuit), (&mut dpqa_spaced, &spaced)] {
+            let transfers = count_transfers(dpqa.solve(circuit).unwrap().instructions());
+            for encoding in [Encoding::BitVec, Encoding::OneHot] {
+                dpqa.set_encoding(encoding);
+                let result = dpqa.solve(circuit).unwrap();
+                assert_eq!(result.verify(circuit), Ok(()));
+                assert_eq!(count_transfers(result.instructions()), transfers);
             }
-        } else {
-            assert!(false)
+        }
+    }
+
+    #[test]
+    /// Symmetry breaking keeps feasibility and the number of transfers
+    fn symmetry_breaking() {
+        let mut circuit = Circuit::new();
+        circuit.append(TwoQubitGate::new(CZ, 0, 2));
+        circuit.append(TwoQubitGate::new(CZ, 1, 3));
+        circuit.append(TwoQubitGate::new(CX, 4, 5));
+        circuit.append(TwoQubitGate::new(CX, 6, 7));
+        circuit.recalculate_stages();
+        let mut dpqa = DPQA::new(2, 2);
+        dpqa.set_symmetry_breaking(true);
+        assert_eq!(dpqa.solve(&circuit), Err(DPQAError::Unsatisfiable));
+
+        // Qubits 2 and 5 are idle
+        let idle_circuit = || {
+            let mut circuit = Circuit::new();
+            circuit.append(TwoQubitGate::new(CZ, 0, 1));
+            circuit.append(TwoQubitGate::new(CZ, 1, 3));
+            circuit.append(TwoQubitGate::new(CZ, 3, 5));
+            circuit
+        };
+        let idle = idle_circuit();
+        let mut measured = idle_circuit();
+        measured.measure(5, 0);
+        // Qubit 2 is idle
+        let mut spaced_circuit = Circuit::new();
+        spaced_circuit.append(TwoQubitGate::new(CZ, 0, 1));
+        spaced_circuit.append(TwoQubitGate::new(CZ, 1, 3));
+
+        let mut spaced = DPQA::new(3, 3);
+        spaced.set_interaction_radius(2);
+        let mut rows = DPQA::new(3, 3);
+        rows.set_addressing(Addressing::Rows(vec![0]));
+        let mut readout = DPQA::new(3, 3);
+        readout.set_readout_zone(vec![2]);
+        readout.set_extra_stages(1);
+        let mut cyclic = DPQA::new(2, 3);
+        cyclic.set_cyclic(true);
+        for (mut dpqa, circuit) in [
+            (DPQA::new(2, 3), &circuit),
+            (DPQA::new(2, 3), &idle),
+            (spaced, &spaced_circuit),
+            (rows, &idle),
+            (readout, &measured),
+            (cyclic, &idle),
+        ] {
+            let transfers = count_transfers(dpqa.solve(circuit).unwrap().instructions());
+            dpqa.set_symmetry_breaking(true);
+            let result = dpqa.solve(circuit).unwrap();
+            assert_eq!(result.verify(circuit), Ok(()));
+            assert_eq!(count_transfers(result.instructions()), transfers);
         }
     }
 }
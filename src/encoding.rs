@@ -0,0 +1,345 @@
+use crate::{dpqa::Encoding, error::DPQAError};
+use std::fmt;
+use z3::{
+    ast::{self, Ast},
+    Context,
+};
+
+/// Range of values 0..size taken by a family of solver variables (e.g. the
+/// x coordinates), and how they are encoded
+#[derive(Clone, Copy, Debug)]
+pub struct Domain<'ctx> {
+    ctx: &'ctx Context,
+    encoding: Encoding,
+    size: u64,
+}
+
+impl<'ctx> Domain<'ctx> {
+    pub fn new(ctx: &'ctx Context, encoding: Encoding, size: u64) -> Domain<'ctx> {
+        Domain {
+            ctx,
+            encoding,
+            size,
+        }
+    }
+
+    /// Number of values in the domain
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Number of bits needed for values up to size - 1
+    fn width(&self) -> u32 {
+        (64 - self.size.saturating_sub(1).leading_zeros()).max(1)
+    }
+
+    /// Create a variable in the domain
+    pub fn var(&self, name: String) -> Coord<'ctx> {
+        match self.encoding {
+            Encoding::Int => Coord::Int(ast::Int::new_const(self.ctx, name)),
+            Encoding::BitVec => Coord::BV(ast::BV::new_const(self.ctx, name, self.width())),
+            Encoding::OneHot => Coord::OneHot {
+                bits: (0..self.size)
+                    .map(|v| ast::Bool::new_const(self.ctx, format!("{}_{}", name, v)))
+                    .collect(),
+                name,
+            },
+        }
+    }
+
+    /// Constant value, which can be compared with variables of any domain
+    pub fn constant(&self, value: u64) -> Coord<'ctx> {
+        Coord::Const {
+            ctx: self.ctx,
+            value,
+        }
+    }
+
+    /// Constraints keeping a variable of the domain in range
+    pub fn bounds(&self, var: &Coord<'ctx>) -> Vec<ast::Bool<'ctx>> {
+        match var {
+            Coord::Const { value, .. } => {
+                vec![ast::Bool::from_bool(self.ctx, *value < self.size)]
+            }
+            Coord::Int(v) => vec![
+                v.ge(&ast::Int::from_u64(self.ctx, 0)),
+                v.lt(&ast::Int::from_u64(self.ctx, self.size)),
+            ],
+            Coord::BV(_) if 2u64.checked_pow(self.width()) == Some(self.size) => {
+                // Every value of the bit-vector is in range
+                vec![]
+            }
+            Coord::BV(_) => vec![var.lt(&self.constant(self.size))],
+            Coord::OneHot { bits, .. } if bits.is_empty() => {
+                vec![ast::Bool::from_bool(self.ctx, false)]
+            }
+            Coord::OneHot { bits, .. } => {
+                // Exactly one value
+                let mut bounds = vec![ast::Bool::or(self.ctx, &bits.iter().collect::<Vec<_>>())];
+                for (ii, b0) in bits.iter().enumerate() {
+                    for b1 in &bits[ii + 1..] {
+                        bounds.push(ast::Bool::and(self.ctx, &[b0, b1]).not());
+                    }
+                }
+                bounds
+            }
+        }
+    }
+}
+
+/// Value of a position, AOD index or stage in the solver, in one of the
+/// encodings of `Encoding`, or a constant
+#[derive(Clone, Debug)]
+pub enum Coord<'ctx> {
+    Const {
+        ctx: &'ctx Context,
+        value: u64,
+    },
+    Int(ast::Int<'ctx>),
+    BV(ast::BV<'ctx>),
+    /// One literal per value, exactly one of which is true
+    OneHot {
+        name: String,
+        bits: Vec<ast::Bool<'ctx>>,
+    },
+}
+
+impl<'ctx> Coord<'ctx> {
+    fn get_ctx(&self) -> &'ctx Context {
+        match self {
+            Coord::Const { ctx, .. } => ctx,
+            Coord::Int(v) => v.get_ctx(),
+            Coord::BV(v) => v.get_ctx(),
+            // Variables have at least one value, as every domain holds 0
+            Coord::OneHot { bits, .. } => bits[0].get_ctx(),
+        }
+    }
+
+    fn as_int(&self) -> ast::Int<'ctx> {
+        match self {
+            Coord::Const { ctx, value } => ast::Int::from_u64(ctx, *value),
+            Coord::Int(v) => v.clone(),
+            _ => unreachable!("variables of different encodings are never compared"),
+        }
+    }
+
+    /// The value as a bit-vector of the given width, if it fits
+    fn as_bv(&self, width: u32) -> Option<ast::BV<'ctx>> {
+        match self {
+            Coord::Const { ctx, value } => {
+                (value.leading_zeros() >= 64 - width).then(|| ast::BV::from_u64(ctx, *value, width))
+            }
+            Coord::BV(v) => Some(v.clone()),
+            _ => unreachable!("variables of different encodings are never compared"),
+        }
+    }
+
+    fn or(ctx: &'ctx Context, args: Vec<ast::Bool<'ctx>>) -> ast::Bool<'ctx> {
+        if args.is_empty() {
+            return ast::Bool::from_bool(ctx, false);
+        }
+        ast::Bool::or(ctx, &args.iter().collect::<Vec<_>>())
+    }
+
+    pub fn _eq(&self, other: &Coord<'ctx>) -> ast::Bool<'ctx> {
+        let ctx = self.get_ctx();
+        match (self, other) {
+            (Coord::Const { value: a, .. }, Coord::Const { value: b, .. }) => {
+                ast::Bool::from_bool(ctx, a == b)
+            }
+            (Coord::Const { .. }, _) => other._eq(self),
+            (Coord::Int(a), _) => a._eq(&other.as_int()),
+            (Coord::BV(a), _) => match other.as_bv(a.get_size()) {
+                Some(b) => a._eq(&b),
+                None => ast::Bool::from_bool(ctx, false),
+            },
+            (Coord::OneHot { bits, .. }, Coord::Const { value, .. }) => {
+                match bits.get(*value as usize) {
+                    Some(b) => b.clone(),
+                    None => ast::Bool::from_bool(ctx, false),
+                }
+            }
+            (Coord::OneHot { bits: a, .. }, Coord::OneHot { bits: b, .. }) => Coord::or(
+                ctx,
+                a.iter()
+                    .zip(b)
+                    .map(|(a, b)| ast::Bool::and(ctx, &[a, b]))
+                    .collect(),
+            ),
+            (Coord::OneHot { .. }, _) => {
+                unreachable!("variables of different encodings are never compared")
+            }
+        }
+    }
+
+    pub fn lt(&self, other: &Coord<'ctx>) -> ast::Bool<'ctx> {
+        let ctx = self.get_ctx();
+        match (self, other) {
+            (Coord::Const { value: a, .. }, Coord::Const { value: b, .. }) => {
+                ast::Bool::from_bool(ctx, a < b)
+            }
+            (Coord::Int(a), _) => a.lt(&other.as_int()),
+            (_, Coord::Int(b)) => self.as_int().lt(b),
+            (Coord::BV(a), _) => match other.as_bv(a.get_size()) {
+                Some(b) => a.bvult(&b),
+                // Larger than any value of `a`
+                None => ast::Bool::from_bool(ctx, true),
+            },
+            (_, Coord::BV(b)) => match self.as_bv(b.get_size()) {
+                Some(a) => a.bvult(b),
+                None => ast::Bool::from_bool(ctx, false),
+            },
+            (Coord::OneHot { bits, .. }, Coord::Const { value, .. }) => {
+                Coord::or(ctx, bits.iter().take(*value as usize).cloned().collect())
+            }
+            (Coord::Const { value, .. }, Coord::OneHot { bits, .. }) => Coord::or(
+                ctx,
+                bits.iter().skip(*value as usize + 1).cloned().collect(),
+            ),
+            (Coord::OneHot { bits: a, .. }, Coord::OneHot { bits: b, .. }) => Coord::or(
+                ctx,
+                b.iter()
+                    .enumerate()
+                    .skip(1)
+                    .map(|(jj, b)| {
+                        let below = Coord::or(ctx, a[..jj].to_vec());
+                        ast::Bool::and(ctx, &[b, &below])
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    pub fn le(&self, other: &Coord<'ctx>) -> ast::Bool<'ctx> {
+        other.lt(self).not()
+    }
+
+    pub fn gt(&self, other: &Coord<'ctx>) -> ast::Bool<'ctx> {
+        other.lt(self)
+    }
+
+    /// True if self - other >= distance
+    pub fn diff_ge(&self, other: &Coord<'ctx>, distance: u64) -> ast::Bool<'ctx> {
+        let ctx = self.get_ctx();
+        match self {
+            Coord::Const { value, .. } if *value >= distance => other.le(&Coord::Const {
+                ctx,
+                value: value - distance,
+            }),
+            Coord::Const { .. } => ast::Bool::from_bool(ctx, false),
+            Coord::Int(a) => {
+                ast::Int::sub(ctx, &[a, &other.as_int()]).ge(&ast::Int::from_u64(ctx, distance))
+            }
+            Coord::BV(a) => {
+                let width = a.get_size();
+                let d = Coord::Const {
+                    ctx,
+                    value: distance,
+                };
+                match (d.as_bv(width), other.as_bv(width)) {
+                    // Subtract only if it does not wrap around
+                    (Some(d), Some(b)) => {
+                        ast::Bool::and(ctx, &[&a.bvuge(&d), &a.bvsub(&d).bvuge(&b)])
+                    }
+                    _ => ast::Bool::from_bool(ctx, false),
+                }
+            }
+            Coord::OneHot { bits, .. } => Coord::or(
+                ctx,
+                bits.iter()
+                    .enumerate()
+                    .skip(distance as usize)
+                    .map(|(ii, a)| {
+                        let b_max = Coord::Const {
+                            ctx,
+                            value: ii as u64 - distance,
+                        };
+                        ast::Bool::and(ctx, &[a, &other.le(&b_max)])
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Value in a model, given the values of the underlying constants
+    pub fn value(
+        &self,
+        get_u64: impl Fn(&ast::Dynamic<'ctx>) -> Result<u64, DPQAError>,
+        get_bool: impl Fn(&ast::Bool<'ctx>) -> Result<bool, DPQAError>,
+    ) -> Result<u64, DPQAError> {
+        match self {
+            Coord::Const { value, .. } => Ok(*value),
+            Coord::Int(v) => get_u64(&ast::Dynamic::from_ast(v)),
+            Coord::BV(v) => get_u64(&ast::Dynamic::from_ast(v)),
+            Coord::OneHot { bits, .. } => {
+                for (ii, b) in bits.iter().enumerate() {
+                    if get_bool(b)? {
+                        return Ok(ii as u64);
+                    }
+                }
+                Err(DPQAError::ModelExtraction(format!("no value for {}", self)))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Coord<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Coord::Const { value, .. } => write!(f, "{}", value),
+            Coord::Int(v) => write!(f, "{}", v),
+            Coord::BV(v) => write!(f, "{}", v),
+            Coord::OneHot { name, .. } => write!(f, "{}", name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Coord, Domain};
+    use crate::dpqa::Encoding;
+    use z3::{ast::Bool, Config, Context, SatResult, Solver};
+
+    /// Check every relation against the integers, for all values in range
+    #[test]
+    fn relations() {
+        let ctx = Context::new(&Config::new());
+        for encoding in [Encoding::Int, Encoding::BitVec, Encoding::OneHot] {
+            let domain = Domain::new(&ctx, encoding, 5);
+            let (a, b) = (domain.var(String::from("a")), domain.var(String::from("b")));
+            for va in 0..5 {
+                for vb in 0..7 {
+                    let solver = Solver::new(&ctx);
+                    for bound in domain.bounds(&a) {
+                        solver.assert(&bound);
+                    }
+                    solver.assert(&a._eq(&domain.constant(va)));
+                    // Out of range constants compare as integers
+                    let out_of_range = domain.constant(vb);
+                    let b: &Coord = if vb < 5 {
+                        for bound in domain.bounds(&b) {
+                            solver.assert(&bound);
+                        }
+                        solver.assert(&b._eq(&domain.constant(vb)));
+                        &b
+                    } else {
+                        &out_of_range
+                    };
+                    let check = |relation: Bool, expected: bool| {
+                        solver.push();
+                        solver.assert(&relation);
+                        let result = solver.check();
+                        solver.pop(1);
+                        assert_eq!(result == SatResult::Sat, expected);
+                    };
+                    check(a._eq(b), va == vb);
+                    check(a.lt(b), va < vb);
+                    check(b.lt(&a), vb < va);
+                    check(a.le(b), va <= vb);
+                    check(a.diff_ge(b, 2), va >= vb + 2);
+                    check(b.diff_ge(&a, 1), vb > va);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use std::error::Error;
+use std::fmt;
+
+/// Reasons why a circuit could not be compiled
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum DPQAError {
+    /// The grid dimensions do not describe a usable architecture
+    InvalidArchitecture(String),
+    /// The grid cannot hold one atom per qubit
+    GridTooSmall { n_qubits: usize, capacity: u64 },
+    /// The circuit does not contain any gates
+    EmptyCircuit,
+    /// The solver proved that no schedule satisfies the constraints
+    Unsatisfiable,
+    /// The solver gave up for a reason other than a timeout
+    SolverUnknown(String),
+    /// The solver found a model, but a value could not be read from it
+    ModelExtraction(String),
+    /// The solver reached the time limit before finding any schedule
+    Timeout,
+    /// A schedule violates the rules of the architecture
+    InvalidSchedule(String),
+    /// The instructions do not implement the input circuit
+    CircuitMismatch(String),
+    /// The circuit has too many qubits for the simulator used to verify it
+    SimulationTooLarge { n_qubits: usize, max_qubits: usize },
+}
+
+impl fmt::Display for DPQAError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DPQAError::InvalidArchitecture(reason) => {
+                write!(f, "Invalid architecture: {}", reason)
+            }
+            DPQAError::GridTooSmall { n_qubits, capacity } => write!(
+                f,
+                "Grid can hold at most {} atoms, but the circuit uses {} qubits",
+                capacity, n_qubits
+            ),
+            DPQAError::EmptyCircuit => write!(f, "Circuit contains no gates"),
+            DPQAError::Unsatisfiable => write!(f, "No schedule satisfies the constraints"),
+            DPQAError::SolverUnknown(reason) => write!(f, "Solver returned unknown: {}", reason),
+            DPQAError::ModelExtraction(reason) => {
+                write!(f, "Could not read solver model: {}", reason)
+            }
+            DPQAError::Timeout => write!(f, "Solver timed out"),
+            DPQAError::InvalidSchedule(reason) => write!(f, "Invalid schedule: {}", reason),
+            DPQAError::CircuitMismatch(reason) => {
+                write!(f, "Schedule does not implement the circuit: {}", reason)
+            }
+            DPQAError::SimulationTooLarge {
+                n_qubits,
+                max_qubits,
+            } => write!(
+                f,
+                "Cannot simulate {} qubits, the simulator supports at most {}",
+                n_qubits, max_qubits
+            ),
+        }
+    }
+}
+
+impl Error for DPQAError {}
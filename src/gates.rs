@@ -2,13 +2,13 @@ use std::fmt;
 
 // Commutation relations for basic two-qubit gates
 
-#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
 pub enum TwoQubitGateType {
     CX,
     CZ,
 }
 
-#[derive(PartialEq, Eq, Clone, Copy)]
+#[derive(PartialEq, Eq, Hash, Clone, Copy)]
 pub struct TwoQubitGate {
     pub gate_type: TwoQubitGateType,
     pub q_ctrl: usize,
@@ -31,6 +31,10 @@ impl TwoQubitGate {
             && self.q_target != gate.q_target
     }
 
+    /// Check whether the two gates can be applied in either order. CX
+    /// gates commute through their control, and CZ gates through either of
+    /// their qubits, so a CX and a CZ commute unless the CZ acts on the CX
+    /// target.
     pub fn commutes_with(&self, gate: &TwoQubitGate) -> bool {
         if self.parallel_with(gate) {
             return true;
@@ -41,11 +45,17 @@ impl TwoQubitGate {
                 TwoQubitGateType::CX => {
                     self.q_ctrl != gate.q_target && self.q_target != gate.q_ctrl
                 }
-                TwoQubitGateType::CZ => self.q_target != gate.q_target,
+                // CZ is symmetric, so the CX target must not be either of
+                // its qubits
+                TwoQubitGateType::CZ => {
+                    self.q_target != gate.q_ctrl && self.q_target != gate.q_target
+                }
             },
 
             TwoQubitGateType::CZ => match gate.gate_type {
-                TwoQubitGateType::CX => self.q_target != gate.q_target,
+                TwoQubitGateType::CX => {
+                    gate.q_target != self.q_ctrl && gate.q_target != self.q_target
+                }
                 TwoQubitGateType::CZ => true,
             },
         }
@@ -68,3 +78,73 @@ impl fmt::Debug for TwoQubitGate {
         write!(f, "{}", self)
     }
 }
+
+/// Single-qubit operations other than gates
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub enum QubitOp {
+    /// Measure a qubit, storing the result in a classical bit
+    Measure { qubit: usize, bit: usize },
+    /// Reset a qubit to |0>
+    Reset(usize),
+}
+
+impl QubitOp {
+    pub fn qubit(&self) -> usize {
+        match *self {
+            QubitOp::Measure { qubit, .. } => qubit,
+            QubitOp::Reset(qubit) => qubit,
+        }
+    }
+}
+
+impl fmt::Display for QubitOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QubitOp::Measure { qubit, bit } => write!(f, "Measure({} -> c{})", qubit, bit),
+            QubitOp::Reset(qubit) => write!(f, "Reset({})", qubit),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TwoQubitGate;
+    use super::TwoQubitGateType::{CX, CZ};
+    use crate::circuit::Circuit;
+
+    #[test]
+    fn commutation() {
+        let cx = TwoQubitGate::new(CX, 0, 1);
+        let cases = [
+            (TwoQubitGate::new(CX, 0, 2), true),
+            (TwoQubitGate::new(CX, 2, 1), true),
+            (TwoQubitGate::new(CX, 1, 2), false),
+            (TwoQubitGate::new(CX, 2, 0), false),
+            (TwoQubitGate::new(CZ, 0, 2), true),
+            (TwoQubitGate::new(CZ, 2, 0), true),
+            // The CX target may be either qubit of the CZ
+            (TwoQubitGate::new(CZ, 1, 2), false),
+            (TwoQubitGate::new(CZ, 2, 1), false),
+            (TwoQubitGate::new(CZ, 0, 1), false),
+            (TwoQubitGate::new(CZ, 2, 3), true),
+        ];
+        for (gate, commutes) in cases {
+            assert_eq!(cx.commutes_with(&gate), commutes, "{} {}", cx, gate);
+            assert_eq!(gate.commutes_with(&cx), commutes, "{} {}", gate, cx);
+        }
+        assert!(TwoQubitGate::new(CZ, 0, 1).commutes_with(&TwoQubitGate::new(CZ, 1, 2)));
+    }
+
+    #[test]
+    fn staging() {
+        // The CZ acts on the target of the CX, so it cannot move before it
+        // into the first stage
+        let mut circuit = Circuit::new();
+        circuit.append(TwoQubitGate::new(CZ, 2, 3));
+        circuit.append(TwoQubitGate::new(CX, 0, 1));
+        circuit.append(TwoQubitGate::new(CZ, 1, 5));
+        circuit.recalculate_stages();
+        assert_eq!(circuit.get_n_stages(), 3);
+        assert_eq!(circuit.get_gate_ordering(), [(0, 1), (1, 2)]);
+    }
+}
@@ -0,0 +1,374 @@
+use crate::{
+    circuit::Circuit,
+    compiler::Compiler,
+    dpqa::{Addressing, FinalLayout, DPQA},
+    error::DPQAError,
+    result::{DPQAResult, Solution},
+    schedule::{DPQAVarsValues, Schedule},
+};
+use std::collections::{BTreeSet, HashSet};
+
+/// Heuristic compiler, which does not need Z3 and runs in milliseconds on
+/// circuits with hundreds of qubits, at the cost of using more stages than
+/// the solver.
+///
+/// Every qubit has a home site in the SLM, spaced by the interaction radius.
+/// Gates are grouped greedily into layers, in circuit order. For each gate
+/// in a layer, one of its atoms is picked up by the AOD, carried to the home
+/// site of the other, and brought back and dropped after the gate. A layer
+/// takes two stages (moving there and back), and the atoms carried in one
+/// layer are picked up in the stage where the previous layer drops its atoms.
+/// Gates whose atoms cannot be carried together (each carried atom needs its
+/// own AOD row and column, in the same order before and after the move) wait
+/// for a later layer.
+///
+/// Locally addressed beams, readout zones and custom final layouts are not
+/// supported. Every atom ends in the SLM at its home site, which is also
+/// where it starts if the final layout must be the initial one.
+/// ```
+/// use dpqa_rs::circuit::Circuit;
+/// use dpqa_rs::compiler::Compiler;
+/// use dpqa_rs::dpqa::DPQA;
+/// use dpqa_rs::gates::{TwoQubitGate, TwoQubitGateType::CZ};
+/// use dpqa_rs::greedy::GreedyCompiler;
+///
+/// let mut circuit = Circuit::new();
+/// for ii in 0..99 {
+///     circuit.append(TwoQubitGate::new(CZ, ii, ii + 1));
+/// }
+/// circuit.recalculate_stages();
+///
+/// let dpqa = DPQA::new(10, 10);
+/// let solution = GreedyCompiler::new(&dpqa).compile(&circuit).unwrap();
+/// assert_eq!(solution.verify(&circuit), Ok(()));
+/// ```
+pub struct GreedyCompiler<'a> {
+    dpqa: &'a DPQA,
+}
+
+/// Atom carried by the AOD to execute a gate
+#[derive(Clone, Copy, Debug)]
+struct Mover {
+    qubit: usize,
+    home: (u64, u64),
+    dest: (u64, u64),
+}
+
+impl Mover {
+    /// Two atoms can be carried at the same time if they are in different
+    /// AOD columns and rows, which stay in the same order during the move
+    fn compatible(&self, other: &Mover) -> bool {
+        let ordered =
+            |a0: u64, a1: u64, b0: u64, b1: u64| a0 != a1 && b0 != b1 && (a0 < a1) == (b0 < b1);
+        ordered(self.home.0, other.home.0, self.dest.0, other.dest.0)
+            && ordered(self.home.1, other.home.1, self.dest.1, other.dest.1)
+    }
+}
+
+/// Position, AOD indices and trap of every qubit at one stage
+#[derive(Clone)]
+struct StageValues {
+    xy: Vec<(u64, u64)>,
+    cr: Vec<(u64, u64)>,
+    aod: Vec<bool>,
+}
+
+impl<'a> GreedyCompiler<'a> {
+    /// Create a compiler for the architecture described by `dpqa`. Solver
+    /// options such as the timeout and extra stages are ignored.
+    pub fn new(dpqa: &'a DPQA) -> GreedyCompiler<'a> {
+        GreedyCompiler { dpqa }
+    }
+
+    /// Reject architecture options the heuristic does not handle
+    fn check_supported(&self) -> Result<(), DPQAError> {
+        let unsupported = if *self.dpqa.get_addressing() != Addressing::Global {
+            "locally addressed beams"
+        } else if self.dpqa.get_readout_zone().is_some() {
+            "readout zones"
+        } else if let FinalLayout::Custom(_) = self.dpqa.get_final_layout() {
+            "custom final layouts"
+        } else {
+            return Ok(());
+        };
+        Err(DPQAError::InvalidArchitecture(format!(
+            "{} are not supported by the greedy compiler",
+            unsupported
+        )))
+    }
+
+    /// Home sites in the SLM, spaced by the interaction radius
+    fn home_sites(&self, n_qubits: usize) -> Result<Vec<(u64, u64)>, DPQAError> {
+        let step = self.dpqa.get_interaction_radius() as usize;
+        let xs: Vec<u64> = (0..self.dpqa.get_cols()).step_by(step).collect();
+        let ys: Vec<u64> = (0..self.dpqa.get_rows()).step_by(step).collect();
+        let capacity = (xs.len() * ys.len()) as u64;
+        if n_qubits as u64 > capacity {
+            return Err(DPQAError::GridTooSmall { n_qubits, capacity });
+        }
+        Ok(ys
+            .iter()
+            .flat_map(|&y| xs.iter().map(move |&x| (x, y)))
+            .take(n_qubits)
+            .collect())
+    }
+
+    /// For each gate, the gates that must run at a later stage: those in
+    /// later stages of the circuit, and those separated from it by a
+    /// measurement or reset on a shared qubit
+    fn successors(circuit: &Circuit) -> Vec<Vec<usize>> {
+        let mut successors = vec![vec![]; circuit.get_n_two_qubit_gates()];
+        for (g0, g1) in circuit.get_gate_ordering() {
+            successors[g0].push(g1);
+        }
+        for (pos, op) in circuit.ops() {
+            let on_qubit: Vec<usize> = circuit
+                .iter()
+                .enumerate()
+                .filter(|(_, g)| g.q_ctrl == op.qubit() || g.q_target == op.qubit())
+                .map(|(ii, _)| ii)
+                .collect();
+            for &g0 in on_qubit.iter().filter(|&&g| g < *pos) {
+                for &g1 in on_qubit.iter().filter(|&&g| g >= *pos) {
+                    successors[g0].push(g1);
+                }
+            }
+        }
+        successors
+    }
+}
+
+impl Compiler for GreedyCompiler<'_> {
+    fn compile(&self, circuit: &Circuit) -> DPQAResult {
+        self.dpqa.check_feasible(circuit)?;
+        self.check_supported()?;
+
+        let n_qubits = circuit.get_n_qubits();
+        let homes = self.home_sites(n_qubits)?;
+        let max_movers = self.dpqa.get_aod_cols().min(self.dpqa.get_aod_rows()) as usize;
+
+        let successors = GreedyCompiler::successors(circuit);
+        let mut n_predecessors = vec![0; successors.len()];
+        successors
+            .iter()
+            .flatten()
+            .for_each(|&g| n_predecessors[g] += 1);
+        let mut ready: BTreeSet<usize> = (0..successors.len())
+            .filter(|&g| n_predecessors[g] == 0)
+            .collect();
+
+        let mut stages = vec![StageValues {
+            xy: homes.clone(),
+            cr: vec![(0, 0); n_qubits],
+            aod: vec![false; n_qubits],
+        }];
+        if self.dpqa.is_cyclic() || *self.dpqa.get_final_layout() == FinalLayout::Initial {
+            // Atoms are picked up for the first gates at the first stage
+            // unless it has to match the last one
+            stages.push(stages[0].clone());
+        }
+        let mut t = vec![0; successors.len()];
+        // Atoms carried in the previous layer are still in the AOD at the
+        // stage before the next pick-up, so they cannot be picked up again
+        let mut recent: HashSet<usize> = HashSet::new();
+
+        while let Some(&first) = ready.first() {
+            let gate_type = circuit.get_gate(first).unwrap().gate_type;
+            let mut busy: HashSet<usize> = HashSet::new();
+            let mut layer: Vec<usize> = vec![];
+            let mut movers: Vec<Mover> = vec![];
+            for &g in &ready {
+                let gate = circuit.get_gate(g).unwrap();
+                if movers.len() == max_movers {
+                    break;
+                }
+                if gate.gate_type != gate_type
+                    || busy.contains(&gate.q_ctrl)
+                    || busy.contains(&gate.q_target)
+                {
+                    continue;
+                }
+                let mover = [(gate.q_target, gate.q_ctrl), (gate.q_ctrl, gate.q_target)]
+                    .into_iter()
+                    .filter(|(moved, _)| !recent.contains(moved))
+                    .map(|(moved, partner)| Mover {
+                        qubit: moved,
+                        home: homes[moved],
+                        dest: homes[partner],
+                    })
+                    .find(|m| movers.iter().all(|other| other.compatible(m)));
+                if let Some(mover) = mover {
+                    movers.push(mover);
+                    layer.push(g);
+                    busy.insert(gate.q_ctrl);
+                    busy.insert(gate.q_target);
+                }
+            }
+
+            if layer.is_empty() {
+                // Every ready gate only has atoms that were just carried, so
+                // wait one stage for them to settle in the SLM
+                stages.push(stages.last().unwrap().clone());
+                recent.clear();
+                continue;
+            }
+
+            // AOD columns and rows in the order of the home sites
+            let mut by_x: Vec<usize> = (0..movers.len()).collect();
+            by_x.sort_by_key(|&ii| movers[ii].home.0);
+            let mut by_y: Vec<usize> = (0..movers.len()).collect();
+            by_y.sort_by_key(|&ii| movers[ii].home.1);
+            let pickup = stages.last_mut().unwrap();
+            for (c, &ii) in by_x.iter().enumerate() {
+                pickup.aod[movers[ii].qubit] = true;
+                pickup.cr[movers[ii].qubit].0 = c as u64;
+            }
+            for (r, &ii) in by_y.iter().enumerate() {
+                pickup.cr[movers[ii].qubit].1 = r as u64;
+            }
+
+            let mut gate_stage = pickup.clone();
+            for m in &movers {
+                gate_stage.xy[m.qubit] = m.dest;
+            }
+            let mut drop_stage = gate_stage.clone();
+            for m in &movers {
+                drop_stage.xy[m.qubit] = m.home;
+                drop_stage.aod[m.qubit] = false;
+            }
+            stages.push(gate_stage);
+            stages.push(drop_stage);
+
+            for &g in &layer {
+                t[g] = (stages.len() - 2) as u64;
+                ready.remove(&g);
+                for &g1 in &successors[g] {
+                    n_predecessors[g1] -= 1;
+                    if n_predecessors[g1] == 0 {
+                        ready.insert(g1);
+                    }
+                }
+            }
+            recent = movers.iter().map(|m| m.qubit).collect();
+        }
+
+        // Each measurement or reset follows the last earlier gate on its
+        // qubit, which comes before any later gate on it
+        let t_ops = circuit
+            .ops()
+            .map(|(pos, op)| {
+                circuit
+                    .iter()
+                    .zip(&t)
+                    .take(*pos)
+                    .filter(|(g, _)| g.q_ctrl == op.qubit() || g.q_target == op.qubit())
+                    .map(|(_, &t)| t)
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+
+        let vals = DPQAVarsValues {
+            xy: (0..n_qubits)
+                .map(|ii| stages.iter().map(|s| s.xy[ii]).collect())
+                .collect(),
+            cr: (0..n_qubits)
+                .map(|ii| stages.iter().map(|s| s.cr[ii]).collect())
+                .collect(),
+            aod: (0..n_qubits)
+                .map(|ii| stages.iter().map(|s| s.aod[ii]).collect())
+                .collect(),
+            t,
+            t_ops,
+        };
+        let schedule = Schedule::from_values(circuit, &vals, self.dpqa.get_addressing())?;
+        Ok(self.dpqa.labelled(Solution::new(schedule)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GreedyCompiler;
+    use crate::circuit::Circuit;
+    use crate::compiler::Compiler;
+    use crate::dpqa::{Addressing, FinalLayout, DPQA};
+    use crate::error::DPQAError;
+    use crate::gates::TwoQubitGate;
+    use crate::gates::TwoQubitGateType::{CX, CZ};
+    #[cfg(feature = "z3")]
+    use std::time::Duration;
+
+    fn example() -> Circuit {
+        let mut circuit = Circuit::new();
+        circuit.append(TwoQubitGate::new(CZ, 0, 2));
+        circuit.append(TwoQubitGate::new(CZ, 1, 3));
+        circuit.append(TwoQubitGate::new(CX, 2, 1));
+        circuit.append(TwoQubitGate::new(CZ, 0, 4));
+        circuit.append(TwoQubitGate::new(CZ, 1, 5));
+        circuit.append(TwoQubitGate::new(CZ, 3, 4));
+        circuit.measure(4, 0);
+        circuit.append(TwoQubitGate::new(CX, 4, 5));
+        circuit.recalculate_stages();
+        circuit
+    }
+
+    #[test]
+    fn compiles() {
+        let circuit = example();
+        let mut dpqa = DPQA::new_aod(3, 3, 2, 2);
+        dpqa.set_final_layout(FinalLayout::Initial);
+        let solution = GreedyCompiler::new(&dpqa).compile(&circuit).unwrap();
+        assert_eq!(solution.verify(&circuit), Ok(()));
+
+        let schedule = solution.schedule();
+        assert!(schedule.is_cyclic());
+        for stage in schedule.iter() {
+            assert!(stage.atoms.iter().filter(|a| a.in_aod()).count() <= 2);
+        }
+
+        let mut dpqa = DPQA::new(2, 2);
+        dpqa.set_addressing(Addressing::Rows(vec![0]));
+        assert!(matches!(
+            GreedyCompiler::new(&dpqa).compile(&circuit),
+            Err(DPQAError::InvalidArchitecture(_))
+        ));
+        let mut dpqa = DPQA::new(3, 3);
+        dpqa.set_interaction_radius(2);
+        assert_eq!(
+            GreedyCompiler::new(&dpqa).compile(&circuit),
+            Err(DPQAError::GridTooSmall {
+                n_qubits: 6,
+                capacity: 4
+            })
+        );
+    }
+
+    #[test]
+    fn large_circuit() {
+        let n = 400;
+        let mut circuit = Circuit::new();
+        for ii in 0..n {
+            circuit.append(TwoQubitGate::new(CZ, ii, (ii * 7 + 3) % n));
+            circuit.append(TwoQubitGate::new(CX, (ii * 13 + 5) % n, ii));
+        }
+        circuit.recalculate_stages();
+
+        let dpqa = DPQA::new(20, 20);
+        let solution = GreedyCompiler::new(&dpqa).compile(&circuit).unwrap();
+        assert_eq!(solution.verify(&circuit), Ok(()));
+    }
+
+    #[cfg(feature = "z3")]
+    #[test]
+    /// Every value chosen by the heuristic satisfies the solver constraints
+    fn solver_accepts() {
+        let circuit = example();
+        let mut dpqa = DPQA::new_aod(3, 3, 2, 2);
+        let solution = GreedyCompiler::new(&dpqa).compile(&circuit).unwrap();
+        dpqa.set_extra_stages(solution.get_n_stages() - circuit.get_n_stages());
+        // Only the first check matters, not how long optimizing takes
+        dpqa.set_timeout(Duration::from_secs(2));
+        let result = dpqa.solve_with_hint(&circuit, solution.schedule()).unwrap();
+        assert_eq!(result.hint_feasible(), Some(true));
+    }
+}
@@ -1,77 +1,208 @@
 use crate::gates::TwoQubitGate;
+use itertools::Itertools;
 use std::fmt;
 
 /// Qubit array instructions
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Eq, Clone, Debug)]
 pub enum DPQAInstruction {
+    /// Place a qubit at a site, either in an SLM trap (`aod == None`) or in
+    /// the given AOD (column, row)
     Init {
         qubit: usize,
         x: u64,
         y: u64,
-        in_aod: bool,
+        aod: Option<(u64, u64)>,
     },
     MoveAODRow {
+        row: u64,
         qubits: Vec<usize>,
         y_from: u64,
         y_to: u64,
     },
     MoveAODCol {
+        col: u64,
         qubits: Vec<usize>,
         x_from: u64,
         x_to: u64,
     },
     MoveToSLM(usize),
-    MoveToAOD(usize),
-    Gate(Vec<TwoQubitGate>),
+    MoveToAOD {
+        qubit: usize,
+        col: u64,
+        row: u64,
+    },
+    /// Turn on an AOD row at the given grid coordinate
+    ActivateAODRow {
+        row: u64,
+        y: u64,
+    },
+    /// Turn on an AOD column at the given grid coordinate
+    ActivateAODCol {
+        col: u64,
+        x: u64,
+    },
+    /// Turn off an AOD row that no longer holds any atoms
+    RetireAODRow(u64),
+    /// Turn off an AOD column that no longer holds any atoms
+    RetireAODCol(u64),
+    /// Execute gates with a Rydberg pulse, either global (`illuminated ==
+    /// None`) or from locally addressed beams at the given (x, y) sites
+    Gate {
+        gates: Vec<TwoQubitGate>,
+        illuminated: Option<Vec<(u64, u64)>>,
+    },
+    /// Measure a qubit, storing the result in a classical bit
+    Measure {
+        qubit: usize,
+        bit: usize,
+    },
+    /// Reset a qubit to |0>
+    Reset(usize),
 }
 
-impl fmt::Display for DPQAInstruction {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let aod_str = |in_aod: &bool| -> &str {
-            if *in_aod {
-                "AOD"
-            } else {
-                "SLM"
+impl DPQAInstruction {
+    /// Get the same instruction, with each qubit index replaced by `label`
+    pub fn relabel(&self, label: impl Fn(usize) -> usize) -> DPQAInstruction {
+        let mut relabelled = self.clone();
+        match &mut relabelled {
+            DPQAInstruction::Init { qubit, .. }
+            | DPQAInstruction::MoveToSLM(qubit)
+            | DPQAInstruction::MoveToAOD { qubit, .. }
+            | DPQAInstruction::Measure { qubit, .. }
+            | DPQAInstruction::Reset(qubit) => *qubit = label(*qubit),
+            DPQAInstruction::MoveAODRow { qubits, .. }
+            | DPQAInstruction::MoveAODCol { qubits, .. } => {
+                qubits.iter_mut().for_each(|q| *q = label(*q));
+            }
+            DPQAInstruction::Gate { gates, .. } => {
+                for g in gates.iter_mut() {
+                    *g = TwoQubitGate::new(g.gate_type, label(g.q_ctrl), label(g.q_target));
+                }
             }
-        };
+            DPQAInstruction::ActivateAODRow { .. }
+            | DPQAInstruction::ActivateAODCol { .. }
+            | DPQAInstruction::RetireAODRow(_)
+            | DPQAInstruction::RetireAODCol(_) => {}
+        }
+        relabelled
+    }
+
+    /// Display the instruction with each qubit shown by `label`, e.g. its
+    /// register name (see `circuit::QubitMap::label`)
+    pub fn display_with<'a>(
+        &'a self,
+        label: impl Fn(usize) -> String + 'a,
+    ) -> impl fmt::Display + 'a {
+        Labelled {
+            instruction: self,
+            label,
+        }
+    }
 
+    fn fmt_with(&self, f: &mut fmt::Formatter, label: &dyn Fn(usize) -> String) -> fmt::Result {
+        let list = |qubits: &[usize]| format!("[{}]", qubits.iter().map(|&q| label(q)).join(", "));
         match self {
-            DPQAInstruction::Init {
-                qubit,
-                x,
-                y,
-                in_aod,
-            } => write!(
-                f,
-                "Initialize qubit {} at x={}, y={} ({})",
-                qubit,
-                x,
-                y,
-                aod_str(in_aod)
-            ),
+            DPQAInstruction::Init { qubit, x, y, aod } => match aod {
+                Some((col, row)) => write!(
+                    f,
+                    "Initialize qubit {} at x={}, y={} (AOD column {}, row {})",
+                    label(*qubit),
+                    x,
+                    y,
+                    col,
+                    row
+                ),
+                None => write!(
+                    f,
+                    "Initialize qubit {} at x={}, y={} (SLM)",
+                    label(*qubit),
+                    x,
+                    y
+                ),
+            },
             DPQAInstruction::MoveAODRow {
+                row,
                 qubits,
                 y_from,
                 y_to,
             } => write!(
                 f,
-                "Move qubit row {:?} from y={} to y={}",
-                qubits, y_from, y_to
+                "Move AOD row {} with qubits {} from y={} to y={}",
+                row,
+                list(qubits),
+                y_from,
+                y_to
             ),
             DPQAInstruction::MoveAODCol {
+                col,
                 qubits,
                 x_from,
                 x_to,
             } => write!(
                 f,
-                "Move qubit column {:?} from x={} to x={}",
-                qubits, x_from, x_to
+                "Move AOD column {} with qubits {} from x={} to x={}",
+                col,
+                list(qubits),
+                x_from,
+                x_to
+            ),
+            DPQAInstruction::MoveToSLM(qubit) => {
+                write!(f, "Transfer qubit {} to SLM", label(*qubit))
+            }
+            DPQAInstruction::MoveToAOD { qubit, col, row } => write!(
+                f,
+                "Transfer qubit {} to AOD column {}, row {}",
+                label(*qubit),
+                col,
+                row
             ),
-            DPQAInstruction::MoveToSLM(qubit) => write!(f, "Transfer qubit {} to SLM", qubit),
-            DPQAInstruction::MoveToAOD(qubit) => write!(f, "Transfer qubit {} to AOD", qubit),
-            DPQAInstruction::Gate(qubit_pairs) => {
-                write!(f, "Execute {:?}", qubit_pairs)
+            DPQAInstruction::ActivateAODRow { row, y } => {
+                write!(f, "Activate AOD row {} at y={}", row, y)
             }
+            DPQAInstruction::ActivateAODCol { col, x } => {
+                write!(f, "Activate AOD column {} at x={}", col, x)
+            }
+            DPQAInstruction::RetireAODRow(row) => write!(f, "Retire AOD row {}", row),
+            DPQAInstruction::RetireAODCol(col) => write!(f, "Retire AOD column {}", col),
+            DPQAInstruction::Gate { gates, illuminated } => {
+                let gates = gates
+                    .iter()
+                    .map(|g| {
+                        format!(
+                            "{:?}({}, {})",
+                            g.gate_type,
+                            label(g.q_ctrl),
+                            label(g.q_target)
+                        )
+                    })
+                    .join(", ");
+                match illuminated {
+                    Some(sites) => write!(f, "Execute [{}] at sites {:?}", gates, sites),
+                    None => write!(f, "Execute [{}]", gates),
+                }
+            }
+            DPQAInstruction::Measure { qubit, bit } => {
+                write!(f, "Measure qubit {} into bit {}", label(*qubit), bit)
+            }
+            DPQAInstruction::Reset(qubit) => write!(f, "Reset qubit {}", label(*qubit)),
         }
     }
 }
+
+/// Instruction displayed with custom qubit labels
+struct Labelled<'a, F> {
+    instruction: &'a DPQAInstruction,
+    label: F,
+}
+
+impl<F: Fn(usize) -> String> fmt::Display for Labelled<'_, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.instruction.fmt_with(f, &self.label)
+    }
+}
+
+impl fmt::Display for DPQAInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_with(f, &|q| q.to_string())
+    }
+}
@@ -1,6 +1,23 @@
+#[cfg(feature = "z3")]
+mod backend;
+pub mod batch;
 pub mod circuit;
+pub mod compiler;
 pub mod dpqa;
+#[cfg(feature = "z3")]
+mod encoding;
+pub mod error;
 pub mod gates;
+pub mod greedy;
 pub mod instruction;
+#[cfg(feature = "z3")]
+pub mod portfolio;
+pub mod progress;
+pub mod render;
 pub mod result;
+pub mod schedule;
+pub mod sequencing;
+pub mod simulate;
+pub mod svg;
+#[cfg(feature = "z3")]
 mod variables;
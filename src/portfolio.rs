@@ -0,0 +1,278 @@
+use crate::{
+    circuit::Circuit,
+    compiler::Compiler,
+    dpqa::DPQA,
+    error::DPQAError,
+    result::{DPQAResult, Solution},
+};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+use z3::{Context, Solver};
+
+/// Signal telling the solvers of a portfolio to stop
+#[derive(Default)]
+pub(crate) struct Cancel {
+    cancelled: Mutex<bool>,
+    changed: Condvar,
+}
+
+impl Cancel {
+    pub fn cancel(&self) {
+        *self.cancelled.lock().unwrap() = true;
+        self.changed.notify_all();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.cancelled.lock().unwrap()
+    }
+
+    /// Run `solve`, interrupting the solvers of the context if cancelled
+    /// before it returns
+    pub fn run<T>(&self, ctx: &Context, solve: impl FnOnce() -> T) -> T {
+        let done = AtomicBool::new(false);
+        let handle = ctx.handle();
+        let result = thread::scope(|s| {
+            s.spawn(|| {
+                let mut cancelled = self.cancelled.lock().unwrap();
+                while !*cancelled && !done.load(Ordering::SeqCst) {
+                    cancelled = self.changed.wait(cancelled).unwrap();
+                }
+                // An interruption only stops the check in progress, and the
+                // solver may start another one, so repeat until it returns
+                while *cancelled && !done.load(Ordering::SeqCst) {
+                    handle.interrupt();
+                    cancelled = self
+                        .changed
+                        .wait_timeout(cancelled, Duration::from_millis(10))
+                        .unwrap()
+                        .0;
+                }
+            });
+            let result = solve();
+            {
+                // Hold the lock so the watcher cannot miss the notification
+                let _cancelled = self.cancelled.lock().unwrap();
+                done.store(true, Ordering::SeqCst);
+            }
+            self.changed.notify_all();
+            result
+        });
+        if self.is_cancelled() {
+            // An interruption outside of a check stays pending until the next
+            // one, and makes evaluating models fail until then
+            Solver::new(ctx).check();
+        }
+        result
+    }
+}
+
+/// Several solver configurations for the same circuit, solved in parallel
+/// threads, e.g. with different numbers of extra stages, encodings, grid
+/// sizes or random seeds.
+/// ```
+/// use dpqa_rs::circuit::Circuit;
+/// use dpqa_rs::dpqa::{Encoding, DPQA};
+/// use dpqa_rs::gates::{TwoQubitGate, TwoQubitGateType::CZ};
+/// use dpqa_rs::portfolio::Portfolio;
+///
+/// let mut circuit = Circuit::new();
+/// circuit.append(TwoQubitGate::new(CZ, 0, 1));
+/// circuit.append(TwoQubitGate::new(CZ, 1, 2));
+///
+/// let mut configs = vec![];
+/// for encoding in [Encoding::Int, Encoding::BitVec, Encoding::OneHot] {
+///     let mut dpqa = DPQA::new(2, 2);
+///     dpqa.set_encoding(encoding);
+///     configs.push(dpqa);
+/// }
+/// let solution = Portfolio::new(configs).solve_first(&circuit).unwrap();
+/// assert_eq!(solution.verify(&circuit), Ok(()));
+/// ```
+pub struct Portfolio {
+    configs: Vec<DPQA>,
+}
+
+impl Portfolio {
+    pub fn new(configs: Vec<DPQA>) -> Portfolio {
+        Portfolio { configs }
+    }
+
+    /// Get the configurations
+    pub fn configs(&self) -> &[DPQA] {
+        &self.configs
+    }
+
+    /// Solve with every configuration, and return the first solution found,
+    /// stopping the other solvers. If every configuration fails, returns
+    /// the error of the first one.
+    pub fn solve_first(&self, circuit: &Circuit) -> DPQAResult {
+        self.solve_all(circuit, true)
+    }
+
+    /// Solve with every configuration, and return the best solution: the
+    /// one with the fewest stages, then the fewest transfers between trap
+    /// types. If every configuration fails, returns the error of the first
+    /// one.
+    pub fn solve_best(&self, circuit: &Circuit) -> DPQAResult {
+        self.solve_all(circuit, false)
+    }
+
+    fn solve_all(&self, circuit: &Circuit, first: bool) -> DPQAResult {
+        if self.configs.is_empty() {
+            return Err(DPQAError::InvalidArchitecture(String::from(
+                "portfolio has no configurations",
+            )));
+        }
+        let cancel = Cancel::default();
+        let (sender, receiver) = mpsc::channel();
+        let mut results: Vec<Option<DPQAResult>> = vec![None; self.configs.len()];
+
+        thread::scope(|s| {
+            for (ii, dpqa) in self.configs.iter().enumerate() {
+                let sender = sender.clone();
+                let cancel = &cancel;
+                s.spawn(move || {
+                    // The receiver only hangs up once a solution was chosen
+                    let _ = sender.send((ii, dpqa.solve_cancellable(circuit, cancel)));
+                });
+            }
+            drop(sender);
+
+            for (ii, result) in receiver {
+                if first && result.is_ok() {
+                    cancel.cancel();
+                    results = vec![None; self.configs.len()];
+                    results[ii] = Some(result);
+                    break;
+                }
+                results[ii] = Some(result);
+            }
+        });
+
+        let key = |s: &Solution| (s.get_n_stages(), s.get_n_transfers());
+        let best = results
+            .iter()
+            .flatten()
+            .filter_map(|r| r.as_ref().ok())
+            .min_by_key(|s| key(s));
+        match best {
+            Some(solution) => Ok(solution.clone()),
+            None => results.swap_remove(0).unwrap(),
+        }
+    }
+}
+
+/// Compile with the first configuration to succeed
+impl Compiler for Portfolio {
+    fn compile(&self, circuit: &Circuit) -> DPQAResult {
+        self.solve_first(circuit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cancel, Portfolio};
+    use crate::circuit::Circuit;
+    use crate::dpqa::{Encoding, DPQA};
+    use crate::error::DPQAError;
+    use crate::gates::{TwoQubitGate, TwoQubitGateType::CX, TwoQubitGateType::CZ};
+    use crate::result::DPQAResult;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<DPQAResult>();
+        assert_send::<DPQA>();
+    }
+
+    #[test]
+    fn first_and_best() {
+        let mut circuit = Circuit::new();
+        circuit.append(TwoQubitGate::new(CZ, 0, 1));
+        circuit.append(TwoQubitGate::new(CZ, 1, 2));
+        circuit.append(TwoQubitGate::new(CZ, 0, 2));
+
+        // Too small, and a slow configuration with an extra stage
+        let mut slow = DPQA::new(4, 4);
+        slow.set_extra_stages(2);
+        slow.set_encoding(Encoding::OneHot);
+        let mut configs = vec![DPQA::new(1, 1), slow, DPQA::new(2, 2)];
+
+        let portfolio = Portfolio::new(configs.clone());
+        let first = portfolio.solve_first(&circuit).unwrap();
+        assert_eq!(first.verify(&circuit), Ok(()));
+        let best = portfolio.solve_best(&circuit).unwrap();
+        assert_eq!(best.verify(&circuit), Ok(()));
+        assert_eq!(best.get_n_stages(), circuit.get_n_stages());
+
+        configs.truncate(1);
+        assert!(matches!(
+            Portfolio::new(configs).solve_first(&circuit),
+            Err(DPQAError::GridTooSmall { .. })
+        ));
+        assert!(Portfolio::new(vec![]).solve_best(&circuit).is_err());
+    }
+
+    #[test]
+    fn cancel() {
+        // Takes minutes to prove unsatisfiable
+        let mut circuit = Circuit::new();
+        for ii in 0..6 {
+            circuit.append(TwoQubitGate::new(CZ, 4 * ii, 4 * ii + 1));
+            circuit.append(TwoQubitGate::new(CX, 4 * ii + 2, 4 * ii + 3));
+        }
+        circuit.recalculate_stages();
+        let dpqa = DPQA::new(3, 4);
+
+        let cancel = Cancel::default();
+        let start = Instant::now();
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(500));
+                cancel.cancel();
+            });
+            let result = dpqa.solve_cancellable(&circuit, &cancel);
+            assert_eq!(result, Err(DPQAError::Timeout));
+        });
+        assert!(start.elapsed() < Duration::from_secs(30));
+    }
+
+    #[test]
+    fn seeds() {
+        let mut circuit = Circuit::new();
+        circuit.append(TwoQubitGate::new(CZ, 0, 1));
+        circuit.append(TwoQubitGate::new(CZ, 2, 3));
+        circuit.append(TwoQubitGate::new(CZ, 1, 2));
+        circuit.append(TwoQubitGate::new(CZ, 0, 3));
+        circuit.recalculate_stages();
+        let configs: Vec<DPQA> = (1..=2)
+            .map(|seed| {
+                let mut dpqa = DPQA::new(2, 3);
+                dpqa.set_random_seed(seed);
+                dpqa
+            })
+            .collect();
+
+        let sequential: Vec<_> = configs
+            .iter()
+            .map(|dpqa| dpqa.solve(&circuit).unwrap().into_instructions())
+            .collect();
+        // Each solver keeps its own seed when they run at the same time
+        let parallel: Vec<_> = thread::scope(|s| {
+            let handles: Vec<_> = configs
+                .iter()
+                .map(|dpqa| s.spawn(|| dpqa.solve(&circuit).unwrap().into_instructions()))
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        assert_eq!(parallel, sequential);
+        assert_eq!(
+            z3::get_global_param("smt.random_seed").as_deref(),
+            Some("0")
+        );
+    }
+}
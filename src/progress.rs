@@ -0,0 +1,100 @@
+use crate::error::DPQAError;
+use std::fmt;
+use std::time::Duration;
+
+/// Function called with the progress of each solve
+pub type SolveObserver = dyn Fn(&SolveEvent) + Send + Sync;
+
+/// Progress of `DPQA::solve`, reported to the observer set with
+/// `DPQA::set_observer`, and logged when the `log` feature is enabled.
+/// Solving may take several attempts, e.g. with more stages to reach a
+/// final layout; each one repeats the events from `AttemptStarted`.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum SolveEvent {
+    /// Started building an instance with the given numbers of stages for
+    /// gates and of stages to reach the final layout
+    AttemptStarted {
+        n_stages: usize,
+        n_return_stages: usize,
+    },
+    /// Created the solver variables
+    VariablesBuilt { n_qubits: usize, n_gates: usize },
+    /// Added a family of constraints or objectives, e.g. `site_crowding`.
+    /// Soft constraints count as assertions.
+    ConstraintsAdded {
+        family: &'static str,
+        n_assertions: usize,
+    },
+    /// Started the solver
+    CheckStarted,
+    /// The solver found a model, after the given time since `CheckStarted`.
+    /// This is reported for the first model and for each better one found
+    /// while optimizing, with the values of the objectives in order (e.g.
+    /// the number of transfers).
+    ModelFound {
+        elapsed: Duration,
+        objectives: Vec<i64>,
+    },
+    /// The attempt failed, e.g. because there are too few stages
+    AttemptFailed(DPQAError),
+    /// Read the schedule from the model
+    SolutionExtracted { n_stages: usize, n_transfers: usize },
+}
+
+impl SolveEvent {
+    #[cfg(all(feature = "log", feature = "z3"))]
+    /// Level at which the event is logged
+    pub(crate) fn level(&self) -> log::Level {
+        match self {
+            SolveEvent::VariablesBuilt { .. } | SolveEvent::ConstraintsAdded { .. } => {
+                log::Level::Debug
+            }
+            _ => log::Level::Info,
+        }
+    }
+}
+
+impl fmt::Display for SolveEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SolveEvent::AttemptStarted {
+                n_stages,
+                n_return_stages,
+            } => write!(
+                f,
+                "Attempt with {} stages and {} return stages",
+                n_stages, n_return_stages
+            ),
+            SolveEvent::VariablesBuilt { n_qubits, n_gates } => {
+                write!(
+                    f,
+                    "Built variables for {} qubits and {} gates",
+                    n_qubits, n_gates
+                )
+            }
+            SolveEvent::ConstraintsAdded {
+                family,
+                n_assertions,
+            } => write!(f, "Added {} assertions for {}", n_assertions, family),
+            SolveEvent::CheckStarted => write!(f, "Solver started"),
+            SolveEvent::ModelFound {
+                elapsed,
+                objectives,
+            } => write!(
+                f,
+                "Model found after {:.3} s with objectives {:?}",
+                elapsed.as_secs_f64(),
+                objectives
+            ),
+            SolveEvent::AttemptFailed(e) => write!(f, "Attempt failed: {}", e),
+            SolveEvent::SolutionExtracted {
+                n_stages,
+                n_transfers,
+            } => write!(
+                f,
+                "Extracted a solution with {} stages and {} transfers",
+                n_stages, n_transfers
+            ),
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use crate::{
+    dpqa::DPQA,
+    instruction::DPQAInstruction,
+    schedule::{Schedule, Stage},
+};
+use itertools::Itertools;
+use std::collections::{BTreeMap, HashSet};
+
+/// Draw one stage of a schedule as text.
+///
+/// Each site of the `rows x cols` grid is drawn as `s/a`, where `s` is the
+/// qubit in the SLM trap and `a` the qubit(s) in AOD traps (`.` if empty).
+/// Sites where a gate is executed are enclosed in brackets. The AOD columns
+/// and rows holding atoms are listed above and to the left of the grid, with
+/// an arrow (`<`, `>`, `^`, `v`) if the line moved into place at this stage.
+/// ```
+/// use dpqa_rs::circuit::Circuit;
+/// use dpqa_rs::compiler::Compiler;
+/// use dpqa_rs::dpqa::DPQA;
+/// use dpqa_rs::gates::{TwoQubitGate, TwoQubitGateType::CZ};
+/// use dpqa_rs::greedy::GreedyCompiler;
+/// use dpqa_rs::render::render_stage;
+///
+/// let mut circuit = Circuit::new();
+/// circuit.append(TwoQubitGate::new(CZ, 0, 1));
+/// circuit.append(TwoQubitGate::new(CZ, 1, 2));
+///
+/// let dpqa = DPQA::new(2, 2);
+/// let solution = GreedyCompiler::new(&dpqa).compile(&circuit).unwrap();
+/// let first = solution.schedule().iter().next().unwrap();
+/// println!("{}", render_stage(&dpqa, first));
+/// ```
+pub fn render_stage(dpqa: &DPQA, stage: &Stage) -> String {
+    let (rows, cols) = (dpqa.get_rows(), dpqa.get_cols());
+
+    // Qubits in SLM and AOD traps at each site
+    let mut slm: BTreeMap<(u64, u64), Vec<usize>> = BTreeMap::new();
+    let mut aod: BTreeMap<(u64, u64), Vec<usize>> = BTreeMap::new();
+    // AOD lines at each coordinate
+    let mut aod_cols: BTreeMap<u64, Vec<u64>> = BTreeMap::new();
+    let mut aod_rows: BTreeMap<u64, Vec<u64>> = BTreeMap::new();
+    for (ii, a) in stage.atoms.iter().enumerate() {
+        match a.aod {
+            Some((c, r)) => {
+                aod.entry((a.x, a.y)).or_default().push(ii);
+                aod_cols.entry(a.x).or_default().push(c);
+                aod_rows.entry(a.y).or_default().push(r);
+            }
+            None => slm.entry((a.x, a.y)).or_default().push(ii),
+        }
+    }
+
+    let gate_sites: HashSet<(u64, u64)> = stage
+        .gates
+        .iter()
+        .filter_map(|g| stage.atoms.get(g.q_ctrl))
+        .map(|a| (a.x, a.y))
+        .collect();
+
+    // Direction of the lines that moved into place
+    let mut arrows: BTreeMap<(bool, u64), char> = BTreeMap::new();
+    for m in &stage.moves {
+        match m {
+            DPQAInstruction::MoveAODCol {
+                col, x_from, x_to, ..
+            } => {
+                arrows.insert((true, *col), if x_to > x_from { '>' } else { '<' });
+            }
+            DPQAInstruction::MoveAODRow {
+                row, y_from, y_to, ..
+            } => {
+                arrows.insert((false, *row), if y_to > y_from { 'v' } else { '^' });
+            }
+            _ => {}
+        }
+    }
+    let line_labels = |prefix: char, is_col: bool, lines: Option<&Vec<u64>>| -> String {
+        lines
+            .map(|v| {
+                v.iter()
+                    .sorted()
+                    .dedup()
+                    .map(|&l| {
+                        let arrow = arrows
+                            .get(&(is_col, l))
+                            .map_or(String::new(), char::to_string);
+                        format!("{}{}{}", prefix, l, arrow)
+                    })
+                    .join(",")
+            })
+            .unwrap_or_default()
+    };
+
+    let qubit_list = |site: Option<&Vec<usize>>| -> String {
+        site.map_or(String::from("."), |v| v.iter().join(","))
+    };
+
+    // Table of cells, starting with the coordinate and AOD column headers
+    let mut table: Vec<Vec<String>> = vec![
+        [String::new(), String::new()]
+            .into_iter()
+            .chain((0..cols).map(|x| format!(" x={}", x)))
+            .collect(),
+        [String::new(), String::new()]
+            .into_iter()
+            .chain((0..cols).map(|x| format!(" {}", line_labels('c', true, aod_cols.get(&x)))))
+            .collect(),
+    ];
+    for y in 0..rows {
+        let mut line = vec![
+            format!("y={}", y),
+            line_labels('r', false, aod_rows.get(&y)),
+        ];
+        for x in 0..cols {
+            let content = format!(
+                "{}/{}",
+                qubit_list(slm.get(&(x, y))),
+                qubit_list(aod.get(&(x, y)))
+            );
+            if gate_sites.contains(&(x, y)) {
+                line.push(format!("[{}]", content));
+            } else {
+                line.push(format!(" {} ", content));
+            }
+        }
+        table.push(line);
+    }
+
+    let widths: Vec<usize> = (0..table[0].len())
+        .map(|jj| table.iter().map(|line| line[jj].len()).max().unwrap_or(0))
+        .collect();
+
+    let mut out = format!("Stage {}", stage.index);
+    if !stage.gates.is_empty() {
+        out += &format!(": Execute {:?}", stage.gates);
+    }
+    out.push('\n');
+    for x in stage.moves.iter().chain(&stage.transfers) {
+        out += &format!("  {}\n", x);
+    }
+    for line in table {
+        let padded = line
+            .iter()
+            .zip(&widths)
+            .map(|(cell, &w)| format!("{:<w$}", cell, w = w))
+            .join(" ");
+        out += padded.trim_end();
+        out.push('\n');
+    }
+    out
+}
+
+/// Draw every stage of a schedule as text, separated by blank lines
+pub fn render_schedule(dpqa: &DPQA, schedule: &Schedule) -> String {
+    schedule
+        .iter()
+        .map(|stage| render_stage(dpqa, stage))
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::render_schedule;
+    use crate::circuit::Circuit;
+    use crate::dpqa::Addressing;
+    use crate::dpqa::DPQA;
+    use crate::gates::TwoQubitGate;
+    use crate::gates::TwoQubitGateType::CZ;
+    use crate::schedule::{DPQAVarsValues, Schedule};
+
+    #[test]
+    fn two_stages() {
+        let mut circuit = Circuit::new();
+        circuit.append(TwoQubitGate::new(CZ, 0, 1));
+        circuit.append(TwoQubitGate::new(CZ, 0, 2));
+
+        let vals = DPQAVarsValues {
+            xy: vec![
+                vec![(0, 0), (1, 0)],
+                vec![(0, 0), (0, 0)],
+                vec![(1, 0), (1, 0)],
+            ],
+            cr: vec![vec![(0, 0), (0, 0)]; 3],
+            aod: vec![vec![true, true], vec![false, false], vec![false, false]],
+            t: vec![0, 1],
+            t_ops: vec![],
+        };
+        let schedule = Schedule::from_values(&circuit, &vals, &Addressing::Global).unwrap();
+        let expected = "\
+Stage 0: Execute [CZ(0, 1)]
+  Activate AOD column 0 at x=0
+  Activate AOD row 0 at y=0
+        x=0   x=1
+        c0
+y=0 r0 [1/0]  2/.
+y=1     ./.   ./.
+
+Stage 1: Execute [CZ(0, 2)]
+  Move AOD column 0 with qubits [0] from x=0 to x=1
+        x=0   x=1
+              c0>
+y=0 r0  1/.  [2/0]
+y=1     ./.   ./.
+";
+        assert_eq!(render_schedule(&DPQA::new(2, 2), &schedule), expected);
+    }
+}
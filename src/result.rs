@@ -1,8 +1,168 @@
-use crate::instruction::DPQAInstruction;
+use crate::{
+    circuit::{Circuit, QubitMap},
+    error::DPQAError,
+    instruction::DPQAInstruction,
+    schedule::{Schedule, Stage},
+    simulate::{verify, Tableau},
+};
 
 /// Compilation result object
-#[derive(PartialEq, Eq, Debug)]
-pub enum DPQAResult {
-    Failed,
-    Succeeded(Vec<DPQAInstruction>),
+pub type DPQAResult = Result<Solution, DPQAError>;
+
+/// Cost of the stages added at the end of a schedule to reach the final
+/// layout (see `DPQA::set_final_layout`)
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct ReturnTrip {
+    /// Number of stages added
+    pub stages: usize,
+    /// Number of AOD row and column moves in those stages
+    pub moves: usize,
+    /// Number of atoms transferred between SLM and AOD traps in those stages
+    pub transfers: usize,
+}
+
+/// Schedule found by a successful compilation
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Solution {
+    schedule: Schedule,
+    instructions: Vec<DPQAInstruction>,
+    n_return_stages: usize,
+    hint_feasible: Option<bool>,
+    optimal: bool,
+    qubit_map: Option<QubitMap>,
+}
+
+impl Solution {
+    pub fn new(schedule: Schedule) -> Solution {
+        Solution::with_return_stages(schedule, 0)
+    }
+
+    /// Create a solution whose last `n_return_stages` stages only move atoms
+    /// into the final layout
+    pub(crate) fn with_return_stages(schedule: Schedule, n_return_stages: usize) -> Solution {
+        Solution {
+            instructions: schedule.to_instructions(),
+            schedule,
+            n_return_stages,
+            hint_feasible: None,
+            optimal: false,
+            qubit_map: None,
+        }
+    }
+
+    #[cfg(feature = "z3")]
+    pub(crate) fn set_hint_feasible(&mut self, hint_feasible: Option<bool>) {
+        self.hint_feasible = hint_feasible;
+    }
+
+    /// Check whether the hint or previous schedule given to the solver could
+    /// be kept as it was. Returns `None` if the solver was not given one.
+    pub fn hint_feasible(&self) -> Option<bool> {
+        self.hint_feasible
+    }
+
+    #[cfg(feature = "z3")]
+    pub(crate) fn set_optimal(&mut self, optimal: bool) {
+        self.optimal = optimal;
+    }
+
+    /// Check whether the solver proved that no schedule with as many stages
+    /// has fewer transfers (then fewer atoms in AOD traps). This is false
+    /// for heuristic compilers, and if the solver timed out or was cancelled
+    /// after finding a schedule: it then returns the best one found so far.
+    pub fn is_optimal(&self) -> bool {
+        self.optimal
+    }
+
+    /// Label the qubits of the instructions with their indices before the
+    /// circuit was renumbered (see `DPQA::set_qubit_map`). Only
+    /// `instructions` is translated: the schedule, and so the renderers,
+    /// keep the renumbered indices.
+    pub fn set_qubit_map(&mut self, map: QubitMap) {
+        self.instructions = self
+            .schedule
+            .instructions()
+            .map(|x| map.restore(&x))
+            .collect();
+        self.qubit_map = Some(map);
+    }
+
+    /// Get the map used to label the qubits of the instructions, if any
+    pub fn qubit_map(&self) -> Option<&QubitMap> {
+        self.qubit_map.as_ref()
+    }
+
+    /// Get the instructions needed to run the circuit. If a qubit map is
+    /// set, the qubits are labelled with their original indices.
+    pub fn instructions(&self) -> &[DPQAInstruction] {
+        &self.instructions
+    }
+
+    /// Consume the solution, returning its instructions
+    pub fn into_instructions(self) -> Vec<DPQAInstruction> {
+        self.instructions
+    }
+
+    /// Get the schedule, grouped into stages
+    pub fn schedule(&self) -> &Schedule {
+        &self.schedule
+    }
+
+    /// Consume the solution, returning its schedule
+    pub fn into_schedule(self) -> Schedule {
+        self.schedule
+    }
+
+    /// Check that the instructions implement `circuit`, using a stabilizer
+    /// simulation (see `simulate::verify`). The circuit is the one that was
+    /// compiled, even if a qubit map is set.
+    pub fn verify(&self, circuit: &Circuit) -> Result<(), DPQAError> {
+        verify::<Tableau>(circuit, &self.schedule.to_instructions())
+    }
+
+    /// Get the number of stages used by the schedule
+    pub fn get_n_stages(&self) -> usize {
+        self.schedule.get_n_stages()
+    }
+
+    /// Get the number of AOD row and column moves over the whole schedule
+    pub fn get_n_moves(&self) -> usize {
+        self.schedule.iter().map(|stage| stage.moves.len()).sum()
+    }
+
+    /// Get the number of atoms transferred between SLM and AOD traps over
+    /// the whole schedule, which is the main cost minimized by the solver
+    pub fn get_n_transfers(&self) -> usize {
+        self.schedule.iter().map(count_transfers).sum()
+    }
+
+    /// Get the cost of the stages added to reach the final layout. These
+    /// are included in the schedule and counted by `get_n_stages`.
+    pub fn return_trip(&self) -> ReturnTrip {
+        let n_stages = self.schedule.get_n_stages();
+        let return_stages = self.schedule.iter().skip(n_stages - self.n_return_stages);
+        let mut trip = ReturnTrip {
+            stages: self.n_return_stages,
+            moves: 0,
+            transfers: 0,
+        };
+        for stage in return_stages {
+            trip.moves += stage.moves.len();
+            trip.transfers += count_transfers(stage);
+        }
+        trip
+    }
+}
+
+fn count_transfers(stage: &Stage) -> usize {
+    stage
+        .transfers
+        .iter()
+        .filter(|x| {
+            matches!(
+                x,
+                DPQAInstruction::MoveToSLM(_) | DPQAInstruction::MoveToAOD { .. }
+            )
+        })
+        .count()
 }
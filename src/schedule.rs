@@ -0,0 +1,593 @@
+use crate::{
+    circuit::Circuit,
+    dpqa::Addressing,
+    error::DPQAError,
+    gates::{QubitOp, TwoQubitGate},
+    instruction::DPQAInstruction,
+    sequencing::{sequence_moves, LineMove},
+};
+use itertools::Itertools;
+use std::collections::BTreeMap;
+
+/// Values of the solver variables (or of a heuristic assignment), from which
+/// a schedule is built: position, AOD indices and trap of each qubit at each
+/// stage, and the stage of each gate, measurement and reset
+pub(crate) struct DPQAVarsValues {
+    pub xy: Vec<Vec<(u64, u64)>>,
+    pub cr: Vec<Vec<(u64, u64)>>,
+    pub aod: Vec<Vec<bool>>,
+    pub t: Vec<u64>,
+    pub t_ops: Vec<u64>,
+}
+
+/// Position and trap of one atom at a given stage
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct AtomState {
+    pub x: u64,
+    pub y: u64,
+    /// AOD (column, row) holding the atom, or `None` if it is in an SLM trap
+    pub aod: Option<(u64, u64)>,
+}
+
+impl AtomState {
+    pub fn in_aod(&self) -> bool {
+        self.aod.is_some()
+    }
+}
+
+/// One stage of a schedule. The moves and transfers lead from the previous
+/// stage into this one, after which `atoms` describes the state of every
+/// qubit, the gates are executed, and then any measurements and resets. At
+/// stage 0 there are no moves, and the transfers only activate the AOD lines
+/// used by the initial configuration.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Stage {
+    pub index: usize,
+    /// AOD row/column moves, in execution order
+    pub moves: Vec<DPQAInstruction>,
+    /// Transfers between SLM and AOD traps, in the order: drops into the
+    /// SLM, retired AOD lines, activated AOD lines, pick-ups by the AOD
+    pub transfers: Vec<DPQAInstruction>,
+    pub gates: Vec<TwoQubitGate>,
+    /// Sites illuminated by locally addressed Rydberg beams to execute the
+    /// gates, or `None` if a global pulse is used
+    pub illuminated: Option<Vec<(u64, u64)>>,
+    /// Measurements and resets, executed after the gates
+    pub ops: Vec<QubitOp>,
+    /// State of each qubit once the moves and transfers are done
+    pub atoms: Vec<AtomState>,
+}
+
+impl Stage {
+    /// Get the instructions for this stage. At stage 0, the initialization
+    /// of every qubit follows the activation of the AOD lines.
+    pub fn instructions(&self) -> impl Iterator<Item = DPQAInstruction> + '_ {
+        let n_init = if self.index == 0 { self.atoms.len() } else { 0 };
+        let init = self
+            .atoms
+            .iter()
+            .enumerate()
+            .take(n_init)
+            .map(|(qubit, a)| DPQAInstruction::Init {
+                qubit,
+                x: a.x,
+                y: a.y,
+                aod: a.aod,
+            });
+        let gates = Some(&self.gates)
+            .filter(|g| !g.is_empty())
+            .map(|g| DPQAInstruction::Gate {
+                gates: g.clone(),
+                illuminated: self.illuminated.clone(),
+            });
+
+        let ops = self.ops.iter().map(|op| match *op {
+            QubitOp::Measure { qubit, bit } => DPQAInstruction::Measure { qubit, bit },
+            QubitOp::Reset(qubit) => DPQAInstruction::Reset(qubit),
+        });
+
+        self.moves
+            .iter()
+            .cloned()
+            .chain(self.transfers.iter().cloned())
+            .chain(init)
+            .chain(gates)
+            .chain(ops)
+    }
+}
+
+/// Closest pair of atoms that are not part of the same gate, at a stage
+/// where gates are executed
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct SpectatorDistance {
+    pub stage: usize,
+    pub qubits: (usize, usize),
+    /// Larger of the x and y separations, in site units
+    pub distance: u64,
+}
+
+/// Compiled schedule, organized into the stages used by the solver
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Schedule {
+    stages: Vec<Stage>,
+}
+
+impl Schedule {
+    pub fn new(stages: Vec<Stage>) -> Schedule {
+        Schedule { stages }
+    }
+
+    /// Build the schedule from the values of the solver variables.
+    ///
+    /// Within each stage, AOD column moves come before AOD row moves, and
+    /// both are ordered by `sequencing::sequence_moves`. Transfers of each
+    /// kind are listed in qubit order, AOD lines in index order, and gates
+    /// in circuit order.
+    ///
+    /// An AOD line is active while it holds at least one atom. If all the
+    /// atoms in a line are dropped into the SLM, the line is retired, even
+    /// if the same index picks up other atoms at the same stage.
+    pub(crate) fn from_values(
+        circuit: &Circuit,
+        vals: &DPQAVarsValues,
+        addressing: &Addressing,
+    ) -> Result<Schedule, DPQAError> {
+        let n_qubits = vals.xy.len();
+        let n_stages = vals.xy.first().map_or(0, |v| v.len());
+
+        let mut stages = Vec::with_capacity(n_stages);
+        for jj in 0..n_stages {
+            let mut moves = Vec::new();
+            let mut transfers = Vec::new();
+
+            if jj == 0 {
+                let (cols, rows) = active_lines(vals, 0, |_| true);
+                for (col, x) in cols {
+                    transfers.push(DPQAInstruction::ActivateAODCol { col, x });
+                }
+                for (row, y) in rows {
+                    transfers.push(DPQAInstruction::ActivateAODRow { row, y });
+                }
+            } else {
+                // Atoms that were in the AOD at the previous stage are
+                // grouped by the line carrying them
+                let mut cols: BTreeMap<u64, LineMove> = BTreeMap::new();
+                let mut rows: BTreeMap<u64, LineMove> = BTreeMap::new();
+                for ii in (0..n_qubits).filter(|&ii| vals.aod[ii][jj - 1]) {
+                    let (x_prev, y_prev) = vals.xy[ii][jj - 1];
+                    let (x_curr, y_curr) = vals.xy[ii][jj];
+                    let (c, r) = vals.cr[ii][jj - 1];
+                    let line = |index, from, to| LineMove {
+                        index,
+                        from,
+                        to,
+                        qubits: vec![],
+                    };
+                    cols.entry(c)
+                        .or_insert(line(c, x_prev, x_curr))
+                        .qubits
+                        .push(ii);
+                    rows.entry(r)
+                        .or_insert(line(r, y_prev, y_curr))
+                        .qubits
+                        .push(ii);
+                }
+                for m in sequence_axis(cols)? {
+                    moves.push(DPQAInstruction::MoveAODCol {
+                        col: m.index,
+                        qubits: m.qubits,
+                        x_from: m.from,
+                        x_to: m.to,
+                    });
+                }
+                for m in sequence_axis(rows)? {
+                    moves.push(DPQAInstruction::MoveAODRow {
+                        row: m.index,
+                        qubits: m.qubits,
+                        y_from: m.from,
+                        y_to: m.to,
+                    });
+                }
+
+                // Lines that keep at least one atom stay active
+                let stays = |ii: usize| vals.aod[ii][jj - 1] && vals.aod[ii][jj];
+                let (kept_cols, kept_rows) = active_lines(vals, jj, stays);
+                let (prev_cols, prev_rows) = active_lines(vals, jj - 1, |_| true);
+                let (curr_cols, curr_rows) = active_lines(vals, jj, |_| true);
+
+                for ii in 0..n_qubits {
+                    if !vals.aod[ii][jj] && vals.aod[ii][jj - 1] {
+                        transfers.push(DPQAInstruction::MoveToSLM(ii));
+                    }
+                }
+                for &col in prev_cols.keys().filter(|c| !kept_cols.contains_key(c)) {
+                    transfers.push(DPQAInstruction::RetireAODCol(col));
+                }
+                for &row in prev_rows.keys().filter(|r| !kept_rows.contains_key(r)) {
+                    transfers.push(DPQAInstruction::RetireAODRow(row));
+                }
+                for (&col, &x) in curr_cols.iter().filter(|(c, _)| !kept_cols.contains_key(c)) {
+                    transfers.push(DPQAInstruction::ActivateAODCol { col, x });
+                }
+                for (&row, &y) in curr_rows.iter().filter(|(r, _)| !kept_rows.contains_key(r)) {
+                    transfers.push(DPQAInstruction::ActivateAODRow { row, y });
+                }
+                for ii in 0..n_qubits {
+                    if vals.aod[ii][jj] && !vals.aod[ii][jj - 1] {
+                        let (col, row) = vals.cr[ii][jj];
+                        transfers.push(DPQAInstruction::MoveToAOD {
+                            qubit: ii,
+                            col,
+                            row,
+                        });
+                    }
+                }
+            }
+
+            // Gate indices are not necessarily sorted by execution time, so
+            // check every gate at each stage
+            let gates: Vec<TwoQubitGate> = circuit
+                .iter()
+                .zip(&vals.t)
+                .filter(|(_, &t)| t as usize == jj)
+                .map(|(&g, _)| g)
+                .collect();
+
+            // Local beams only illuminate the sites where gates are executed
+            let illuminated = match addressing {
+                Addressing::Global => None,
+                _ => Some(
+                    gates
+                        .iter()
+                        .map(|g| vals.xy[g.q_ctrl][jj])
+                        .sorted()
+                        .dedup()
+                        .collect(),
+                ),
+            };
+
+            let ops = circuit
+                .ops()
+                .zip(&vals.t_ops)
+                .filter(|(_, &t)| t as usize == jj)
+                .map(|(&(_, op), _)| op)
+                .collect();
+
+            let atoms = (0..n_qubits)
+                .map(|ii| AtomState {
+                    x: vals.xy[ii][jj].0,
+                    y: vals.xy[ii][jj].1,
+                    aod: Some(vals.cr[ii][jj]).filter(|_| vals.aod[ii][jj]),
+                })
+                .collect();
+
+            stages.push(Stage {
+                index: jj,
+                moves,
+                transfers,
+                gates,
+                illuminated,
+                ops,
+                atoms,
+            });
+        }
+
+        Ok(Schedule::new(stages))
+    }
+
+    /// Get the number of stages
+    pub fn get_n_stages(&self) -> usize {
+        self.stages.len()
+    }
+
+    /// Get the nth stage, or `None` if there are fewer than n + 1 stages
+    pub fn get_stage(&self, n: usize) -> Option<&Stage> {
+        self.stages.get(n)
+    }
+
+    /// Get an iterator over the stages
+    pub fn iter(&self) -> std::slice::Iter<'_, Stage> {
+        self.stages.iter()
+    }
+
+    /// Get an iterator over the flattened list of instructions
+    pub fn instructions(&self) -> impl Iterator<Item = DPQAInstruction> + '_ {
+        self.stages.iter().flat_map(|s| s.instructions())
+    }
+
+    /// Convert to a flat list of instructions
+    pub fn to_instructions(&self) -> Vec<DPQAInstruction> {
+        self.instructions().collect()
+    }
+
+    /// Check whether the schedule can be repeated, i.e. whether the last
+    /// stage has no gates and returns all atoms to their initial state
+    pub fn is_cyclic(&self) -> bool {
+        match (self.stages.first(), self.stages.last()) {
+            (Some(first), Some(last)) => {
+                self.stages.len() > 1
+                    && last.gates.is_empty()
+                    && last.ops.is_empty()
+                    && first.atoms == last.atoms
+            }
+            _ => false,
+        }
+    }
+
+    /// Get the instructions for repeating a cyclic schedule the given number
+    /// of times. After the first repetition, the atoms are already in place
+    /// for the gates of stage 0, so only its gates, measurements and resets
+    /// are repeated.
+    pub fn unroll(&self, repetitions: usize) -> Result<Vec<DPQAInstruction>, DPQAError> {
+        if !self.is_cyclic() {
+            return Err(DPQAError::InvalidSchedule(String::from(
+                "schedule does not return the atoms to their initial state",
+            )));
+        }
+
+        let mut instructions = Vec::new();
+        for ii in 0..repetitions {
+            let (first, rest) = self.stages.split_first().unwrap();
+            if ii == 0 {
+                instructions.extend(first.instructions());
+            } else {
+                instructions.extend(first.instructions().filter(|inst| {
+                    matches!(
+                        inst,
+                        DPQAInstruction::Gate { .. }
+                            | DPQAInstruction::Measure { .. }
+                            | DPQAInstruction::Reset(_)
+                    )
+                }));
+            }
+            instructions.extend(rest.iter().flat_map(|s| s.instructions()));
+        }
+        Ok(instructions)
+    }
+
+    /// Find the closest pair of atoms that are exposed to the Rydberg laser
+    /// without being part of the same gate (the first such pair if there are
+    /// several). With local addressing, only the atoms on illuminated sites
+    /// are exposed. Returns `None` if no stage with gates has two or more
+    /// exposed atoms.
+    pub fn min_spectator_distance(&self) -> Option<SpectatorDistance> {
+        let mut closest: Option<SpectatorDistance> = None;
+        for stage in self.stages.iter().filter(|s| !s.gates.is_empty()) {
+            let exposed = stage.atoms.iter().enumerate().filter(|(_, a)| {
+                stage
+                    .illuminated
+                    .as_ref()
+                    .is_none_or(|sites| sites.contains(&(a.x, a.y)))
+            });
+            for ((q0, a0), (q1, a1)) in exposed.tuple_combinations() {
+                let same_gate = stage.gates.iter().any(|g| {
+                    (g.q_ctrl, g.q_target) == (q0, q1) || (g.q_ctrl, g.q_target) == (q1, q0)
+                });
+                if same_gate {
+                    continue;
+                }
+                let distance = a0.x.abs_diff(a1.x).max(a0.y.abs_diff(a1.y));
+                if closest.is_none_or(|c| distance < c.distance) {
+                    closest = Some(SpectatorDistance {
+                        stage: stage.index,
+                        qubits: (q0, q1),
+                        distance,
+                    });
+                }
+            }
+        }
+        closest
+    }
+}
+
+/// Get the AOD columns and rows holding at least one of the selected atoms
+/// at the given stage, mapped to their grid coordinates
+fn active_lines(
+    vals: &DPQAVarsValues,
+    stage: usize,
+    select: impl Fn(usize) -> bool,
+) -> (BTreeMap<u64, u64>, BTreeMap<u64, u64>) {
+    let mut cols = BTreeMap::new();
+    let mut rows = BTreeMap::new();
+    for ii in (0..vals.aod.len()).filter(|&ii| vals.aod[ii][stage] && select(ii)) {
+        let (c, r) = vals.cr[ii][stage];
+        let (x, y) = vals.xy[ii][stage];
+        cols.entry(c).or_insert(x);
+        rows.entry(r).or_insert(y);
+    }
+    (cols, rows)
+}
+
+/// Order the moves of the active AOD lines along one axis, given every
+/// active line keyed by its index
+fn sequence_axis(lines: BTreeMap<u64, LineMove>) -> Result<Vec<LineMove>, DPQAError> {
+    let positions = lines.iter().map(|(&ii, m)| (ii, m.from)).collect();
+    let moves = lines.into_values().filter(|m| m.from != m.to).collect();
+    sequence_moves(&positions, moves)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DPQAVarsValues, Schedule, SpectatorDistance};
+    use crate::circuit::Circuit;
+    use crate::dpqa::Addressing;
+    use crate::gates::TwoQubitGate;
+    use crate::gates::TwoQubitGateType::CZ;
+    use crate::instruction::DPQAInstruction;
+
+    /// Qubit 0 is carried by the AOD from qubit 1 to qubit 2, then moved
+    /// to an empty site and dropped into the SLM
+    fn example() -> (Circuit, DPQAVarsValues) {
+        let mut circuit = Circuit::new();
+        circuit.append(TwoQubitGate::new(CZ, 0, 1));
+        circuit.append(TwoQubitGate::new(CZ, 0, 2));
+
+        let vals = DPQAVarsValues {
+            xy: vec![
+                vec![(0, 0), (1, 0), (1, 1)],
+                vec![(0, 0), (0, 0), (0, 0)],
+                vec![(1, 0), (1, 0), (1, 0)],
+            ],
+            cr: vec![vec![(0, 0), (0, 0), (0, 0)]; 3],
+            aod: vec![
+                vec![true, true, false],
+                vec![false, false, false],
+                vec![false, false, false],
+            ],
+            t: vec![0, 1],
+            t_ops: vec![],
+        };
+        (circuit, vals)
+    }
+
+    #[test]
+    fn stages() {
+        let (circuit, vals) = example();
+        let schedule = Schedule::from_values(&circuit, &vals, &Addressing::Global).unwrap();
+        assert_eq!(schedule.get_n_stages(), 3);
+
+        let stage = schedule.get_stage(1).unwrap();
+        assert_eq!(stage.index, 1);
+        assert_eq!(stage.gates, vec![TwoQubitGate::new(CZ, 0, 2)]);
+        assert_eq!(stage.atoms[0].aod, Some((0, 0)));
+        assert_eq!((stage.atoms[0].x, stage.atoms[0].y), (1, 0));
+
+        let last = schedule.get_stage(2).unwrap();
+        assert_eq!(
+            last.moves,
+            vec![DPQAInstruction::MoveAODRow {
+                row: 0,
+                qubits: vec![0],
+                y_from: 0,
+                y_to: 1
+            }]
+        );
+        assert_eq!(
+            last.transfers,
+            vec![
+                DPQAInstruction::MoveToSLM(0),
+                DPQAInstruction::RetireAODCol(0),
+                DPQAInstruction::RetireAODRow(0)
+            ]
+        );
+        assert!(!last.atoms[0].in_aod());
+    }
+
+    #[test]
+    fn not_cyclic() {
+        let (circuit, vals) = example();
+        let schedule = Schedule::from_values(&circuit, &vals, &Addressing::Global).unwrap();
+        assert!(!schedule.is_cyclic());
+        assert!(schedule.unroll(2).is_err());
+    }
+
+    #[test]
+    fn spectators() {
+        let (circuit, vals) = example();
+        let schedule = Schedule::from_values(&circuit, &vals, &Addressing::Global).unwrap();
+        assert_eq!(
+            schedule.min_spectator_distance(),
+            Some(SpectatorDistance {
+                stage: 0,
+                qubits: (0, 2),
+                distance: 1
+            })
+        );
+
+        // Qubit 2 is next to the first gate, but its site is not illuminated
+        let addressing = Addressing::Rows(vec![0]);
+        let schedule = Schedule::from_values(&circuit, &vals, &addressing).unwrap();
+        assert_eq!(schedule.min_spectator_distance(), None);
+    }
+
+    #[test]
+    fn flattened() {
+        let (circuit, vals) = example();
+        let schedule = Schedule::from_values(&circuit, &vals, &Addressing::Global).unwrap();
+        let instructions = schedule.to_instructions();
+        assert_eq!(instructions.len(), 12);
+        assert_eq!(
+            instructions[..6],
+            [
+                DPQAInstruction::ActivateAODCol { col: 0, x: 0 },
+                DPQAInstruction::ActivateAODRow { row: 0, y: 0 },
+                DPQAInstruction::Init {
+                    qubit: 0,
+                    x: 0,
+                    y: 0,
+                    aod: Some((0, 0))
+                },
+                DPQAInstruction::Init {
+                    qubit: 1,
+                    x: 0,
+                    y: 0,
+                    aod: None
+                },
+                DPQAInstruction::Init {
+                    qubit: 2,
+                    x: 1,
+                    y: 0,
+                    aod: None
+                },
+                DPQAInstruction::Gate {
+                    gates: vec![TwoQubitGate::new(CZ, 0, 1)],
+                    illuminated: None
+                },
+            ]
+        );
+        assert_eq!(
+            instructions[6],
+            DPQAInstruction::MoveAODCol {
+                col: 0,
+                qubits: vec![0],
+                x_from: 0,
+                x_to: 1
+            }
+        );
+        assert_eq!(instructions[9], DPQAInstruction::MoveToSLM(0));
+        assert_eq!(
+            schedule.iter().flat_map(|s| s.instructions()).count(),
+            instructions.len()
+        );
+    }
+
+    #[test]
+    /// Dropping the only atom in a line retires it, even if the same index
+    /// picks up another atom
+    fn reused_line() {
+        let mut circuit = Circuit::new();
+        circuit.append(TwoQubitGate::new(CZ, 0, 1));
+
+        let vals = DPQAVarsValues {
+            xy: vec![
+                vec![(0, 0), (0, 0)],
+                vec![(0, 0), (0, 0)],
+                vec![(1, 0), (1, 0)],
+            ],
+            cr: vec![
+                vec![(0, 0), (0, 0)],
+                vec![(0, 0), (0, 0)],
+                vec![(0, 0), (0, 0)],
+            ],
+            aod: vec![vec![true, false], vec![false, false], vec![false, true]],
+            t: vec![0],
+            t_ops: vec![],
+        };
+        let schedule = Schedule::from_values(&circuit, &vals, &Addressing::Global).unwrap();
+        assert_eq!(
+            schedule.get_stage(1).unwrap().transfers,
+            vec![
+                DPQAInstruction::MoveToSLM(0),
+                DPQAInstruction::RetireAODCol(0),
+                DPQAInstruction::RetireAODRow(0),
+                DPQAInstruction::ActivateAODCol { col: 0, x: 1 },
+                DPQAInstruction::ActivateAODRow { row: 0, y: 0 },
+                DPQAInstruction::MoveToAOD {
+                    qubit: 2,
+                    col: 0,
+                    row: 0
+                },
+            ]
+        );
+    }
+}
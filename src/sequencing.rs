@@ -0,0 +1,156 @@
+use crate::error::DPQAError;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+
+/// Maximum number of consecutive AOD lines that may be stacked at the same
+/// grid coordinate. Lines whose indices differ by at least this much must be
+/// strictly separated.
+pub const AOD_MAX_STACK: u64 = 3;
+
+/// Move of one AOD row or column between two stages
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct LineMove {
+    /// Index of the AOD row/column
+    pub index: u64,
+    /// Grid coordinate before the move
+    pub from: u64,
+    /// Grid coordinate after the move
+    pub to: u64,
+    /// Qubits carried by the line
+    pub qubits: Vec<usize>,
+}
+
+/// Check that a set of AOD line positions (line index -> grid coordinate)
+/// respects the ordering and crowding rules: lines must be in the same order
+/// as their indices, and lines whose indices differ by `AOD_MAX_STACK` or
+/// more may not share a coordinate.
+pub fn lines_valid(positions: &BTreeMap<u64, u64>) -> bool {
+    let lines: Vec<(u64, u64)> = positions.iter().map(|(&ii, &p)| (ii, p)).collect();
+    for (jj, &(i1, p1)) in lines.iter().enumerate() {
+        for &(i0, p0) in &lines[..jj] {
+            if p0 > p1 || (i1 - i0 >= AOD_MAX_STACK && p0 == p1) {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+/// Order the moves of AOD lines along one axis so that they can be executed
+/// one at a time. `positions` maps the index of every active line to its
+/// coordinate before the moves.
+///
+/// Lines moving towards higher coordinates go first, starting from the
+/// highest index, followed by lines moving towards lower coordinates,
+/// starting from the lowest index. Since lines cannot pass each other, a
+/// move is safe if the configuration after it satisfies `lines_valid`, and
+/// with this order every intermediate configuration is valid whenever the
+/// start and end configurations are. Each step is checked, and an error is
+/// returned if any configuration is invalid.
+pub fn sequence_moves(
+    positions: &BTreeMap<u64, u64>,
+    moves: Vec<LineMove>,
+) -> Result<Vec<LineMove>, DPQAError> {
+    if !lines_valid(positions) {
+        return Err(DPQAError::InvalidSchedule(format!(
+            "AOD lines out of order before moving: {:?}",
+            positions
+        )));
+    }
+
+    let (mut increasing, mut decreasing): (Vec<_>, Vec<_>) =
+        moves.into_iter().partition(|m| m.to > m.from);
+    increasing.sort_by_key(|m| Reverse(m.index));
+    decreasing.sort_by_key(|m| m.index);
+    let mut ordered = increasing;
+    ordered.append(&mut decreasing);
+
+    let mut current = positions.clone();
+    for m in &ordered {
+        current.insert(m.index, m.to);
+        if !lines_valid(&current) {
+            return Err(DPQAError::InvalidSchedule(format!(
+                "moving AOD line {} from {} to {} gives invalid positions {:?}",
+                m.index, m.from, m.to, current
+            )));
+        }
+    }
+    Ok(ordered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lines_valid, sequence_moves, LineMove};
+    use std::collections::BTreeMap;
+
+    fn line_move(index: u64, from: u64, to: u64) -> LineMove {
+        LineMove {
+            index,
+            from,
+            to,
+            qubits: vec![index as usize],
+        }
+    }
+
+    #[test]
+    fn valid_configurations() {
+        assert!(lines_valid(&BTreeMap::from([
+            (0, 0),
+            (1, 0),
+            (2, 0),
+            (3, 1)
+        ])));
+        // Lines out of order
+        assert!(!lines_valid(&BTreeMap::from([(0, 1), (1, 0)])));
+        // Four lines stacked at the same coordinate
+        assert!(!lines_valid(&BTreeMap::from([
+            (0, 0),
+            (1, 0),
+            (2, 0),
+            (3, 0)
+        ])));
+        // Lines 1 and 4 differ by AOD_MAX_STACK, so they cannot share a coordinate
+        assert!(!lines_valid(&BTreeMap::from([(1, 2), (4, 2)])));
+    }
+
+    #[test]
+    fn preferred_order() {
+        let positions = BTreeMap::from([(0, 0), (1, 2), (2, 2), (3, 4)]);
+        let moves = vec![
+            line_move(0, 0, 1),
+            line_move(1, 2, 1),
+            line_move(2, 2, 3),
+            line_move(3, 4, 3),
+        ];
+        let order: Vec<u64> = sequence_moves(&positions, moves)
+            .unwrap()
+            .iter()
+            .map(|m| m.index)
+            .collect();
+        assert_eq!(order, vec![2, 0, 1, 3]);
+    }
+
+    #[test]
+    /// Moving line 0 first would stack four lines at x = 1
+    fn avoid_crowding() {
+        let positions = BTreeMap::from([(0, 0), (1, 1), (2, 1), (3, 1)]);
+        let moves = vec![line_move(0, 0, 1), line_move(3, 1, 2)];
+        let order: Vec<u64> = sequence_moves(&positions, moves)
+            .unwrap()
+            .iter()
+            .map(|m| m.index)
+            .collect();
+        assert_eq!(order, vec![3, 0]);
+    }
+
+    #[test]
+    fn impossible_moves() {
+        // Line 1 would have to pass line 0
+        let positions = BTreeMap::from([(0, 1), (1, 2)]);
+        assert!(sequence_moves(&positions, vec![line_move(1, 2, 0)]).is_err());
+
+        // Start configuration is already crowded
+        let positions = BTreeMap::from([(0, 1), (3, 1)]);
+        assert!(sequence_moves(&positions, vec![]).is_err());
+    }
+}
@@ -0,0 +1,305 @@
+use crate::{
+    circuit::Circuit,
+    error::DPQAError,
+    gates::{TwoQubitGate, TwoQubitGateType},
+    instruction::DPQAInstruction,
+};
+use std::collections::HashMap;
+
+/// Simulator for circuits made of two-qubit gates. Two simulators are equal
+/// if the gates applied so far implement the same operation.
+pub trait Simulator: PartialEq + Sized {
+    /// Create a simulator for `n_qubits` qubits, or fail if the simulation
+    /// would not fit in memory
+    fn new(n_qubits: usize) -> Result<Self, DPQAError>;
+    fn apply(&mut self, gate: &TwoQubitGate);
+}
+
+/// State vector simulator. CX and CZ map basis states to basis states (up to
+/// a sign), so starting from a state whose amplitudes all have different
+/// magnitudes, two gate sequences give the same state exactly when they
+/// implement the same unitary. Amplitudes are integers, so the comparison is
+/// exact. Memory use grows as 2^n, so this is only usable for small circuits
+/// (up to `StateVector::MAX_QUBITS` qubits).
+#[derive(PartialEq, Eq, Debug)]
+pub struct StateVector {
+    amplitudes: Vec<i64>,
+}
+
+impl StateVector {
+    /// Largest number of qubits simulated, using 128 MiB of amplitudes
+    pub const MAX_QUBITS: usize = 24;
+}
+
+impl Simulator for StateVector {
+    fn new(n_qubits: usize) -> Result<StateVector, DPQAError> {
+        if n_qubits > StateVector::MAX_QUBITS {
+            return Err(DPQAError::SimulationTooLarge {
+                n_qubits,
+                max_qubits: StateVector::MAX_QUBITS,
+            });
+        }
+        Ok(StateVector {
+            amplitudes: (1..=(1_i64 << n_qubits)).collect(),
+        })
+    }
+
+    fn apply(&mut self, gate: &TwoQubitGate) {
+        let (a, b) = (1_usize << gate.q_ctrl, 1_usize << gate.q_target);
+        for ii in 0..self.amplitudes.len() {
+            match gate.gate_type {
+                TwoQubitGateType::CX => {
+                    if ii & a != 0 && ii & b == 0 {
+                        self.amplitudes.swap(ii, ii | b);
+                    }
+                }
+                TwoQubitGateType::CZ => {
+                    if ii & a != 0 && ii & b != 0 {
+                        self.amplitudes[ii] = -self.amplitudes[ii];
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Stabilizer tableau simulator (Aaronson and Gottesman, 2004). The tableau
+/// tracks the image of every single-qubit Pauli X and Z operator, including
+/// signs, so it describes the whole Clifford unitary up to a global phase.
+/// Each gate takes O(n) time, so this scales to hundreds of qubits.
+#[derive(PartialEq, Eq, Debug)]
+pub struct Tableau {
+    /// Rows 0..n are the destabilizers (images of X), rows n..2n the
+    /// stabilizers (images of Z)
+    x: Vec<Vec<bool>>,
+    z: Vec<Vec<bool>>,
+    sign: Vec<bool>,
+}
+
+impl Simulator for Tableau {
+    fn new(n_qubits: usize) -> Result<Tableau, DPQAError> {
+        let mut x = vec![vec![false; n_qubits]; 2 * n_qubits];
+        let mut z = vec![vec![false; n_qubits]; 2 * n_qubits];
+        for ii in 0..n_qubits {
+            x[ii][ii] = true;
+            z[n_qubits + ii][ii] = true;
+        }
+        Ok(Tableau {
+            x,
+            z,
+            sign: vec![false; 2 * n_qubits],
+        })
+    }
+
+    fn apply(&mut self, gate: &TwoQubitGate) {
+        let (a, b) = (gate.q_ctrl, gate.q_target);
+        for ((x, z), sign) in self.x.iter_mut().zip(&mut self.z).zip(&mut self.sign) {
+            match gate.gate_type {
+                TwoQubitGateType::CX => {
+                    *sign ^= x[a] && z[b] && !(x[b] ^ z[a]);
+                    x[b] ^= x[a];
+                    z[a] ^= z[b];
+                }
+                TwoQubitGateType::CZ => {
+                    *sign ^= x[a] && x[b] && (z[a] ^ z[b]);
+                    z[a] ^= x[b];
+                    z[b] ^= x[a];
+                }
+            }
+        }
+    }
+}
+
+/// Check that the gates in a list of instructions implement the circuit. Each
+/// gate must appear exactly as often as in the circuit, and the resulting
+/// operation (simulated with `S`) must be the same as that of the circuit,
+/// so gates can only be reordered if they commute.
+pub fn verify<S: Simulator>(
+    circuit: &Circuit,
+    instructions: &[DPQAInstruction],
+) -> Result<(), DPQAError> {
+    let scheduled: Vec<TwoQubitGate> = instructions
+        .iter()
+        .filter_map(|inst| match inst {
+            DPQAInstruction::Gate { gates, .. } => Some(gates),
+            _ => None,
+        })
+        .flatten()
+        .copied()
+        .collect();
+
+    let mut counts: HashMap<TwoQubitGate, i64> = HashMap::new();
+    for g in circuit.iter() {
+        *counts.entry(*g).or_insert(0) += 1;
+    }
+    for g in &scheduled {
+        *counts.entry(*g).or_insert(0) -= 1;
+    }
+    // Report the first gate (in circuit order) whose count is off
+    for g in circuit.iter().chain(&scheduled) {
+        match counts[g] {
+            0 => {}
+            n if n > 0 => {
+                return Err(DPQAError::CircuitMismatch(format!(
+                    "{:?} was dropped {} time(s)",
+                    g, n
+                )))
+            }
+            n => {
+                return Err(DPQAError::CircuitMismatch(format!(
+                    "{:?} was added {} time(s)",
+                    g, -n
+                )))
+            }
+        }
+    }
+
+    let mut expected = S::new(circuit.get_n_qubits())?;
+    circuit.iter().for_each(|g| expected.apply(g));
+    let mut actual = S::new(circuit.get_n_qubits())?;
+    scheduled.iter().for_each(|g| actual.apply(g));
+    if expected != actual {
+        return Err(DPQAError::CircuitMismatch(String::from(
+            "gates were reordered in a way that changes the result",
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{verify, Simulator, StateVector, Tableau};
+    use crate::circuit::Circuit;
+    #[cfg(feature = "z3")]
+    use crate::dpqa::DPQA;
+    use crate::error::DPQAError;
+    use crate::gates::TwoQubitGate;
+    use crate::gates::TwoQubitGateType::{CX, CZ};
+    use crate::instruction::DPQAInstruction;
+
+    fn run<S: Simulator>(n_qubits: usize, gates: &[TwoQubitGate]) -> S {
+        let mut sim = S::new(n_qubits).unwrap();
+        gates.iter().for_each(|g| sim.apply(g));
+        sim
+    }
+
+    #[test]
+    fn backends_agree() {
+        let mut gates = vec![];
+        for (a, b) in [(0, 1), (1, 0), (0, 2), (2, 0), (1, 2), (2, 1)] {
+            gates.push(TwoQubitGate::new(CX, a, b));
+            gates.push(TwoQubitGate::new(CZ, a, b));
+        }
+        for g in &gates {
+            for h in &gates {
+                for k in &gates {
+                    let (lhs, rhs) = ([*g, *h, *k], [*k, *h, *g]);
+                    assert_eq!(
+                        run::<StateVector>(3, &lhs) == run::<StateVector>(3, &rhs),
+                        run::<Tableau>(3, &lhs) == run::<Tableau>(3, &rhs),
+                    );
+                }
+            }
+        }
+
+        // Self-inverse gates
+        let cz = TwoQubitGate::new(CZ, 0, 1);
+        let cx = TwoQubitGate::new(CX, 1, 0);
+        assert_eq!(
+            run::<StateVector>(2, &[cz, cz]),
+            StateVector::new(2).unwrap()
+        );
+        assert_eq!(run::<Tableau>(2, &[cx, cx]), Tableau::new(2).unwrap());
+        assert_ne!(run::<Tableau>(2, &[cz, cx]), run::<Tableau>(2, &[cx, cz]));
+    }
+
+    #[test]
+    fn detect_errors() {
+        let mut circuit = Circuit::new();
+        let gates = [
+            TwoQubitGate::new(CZ, 0, 1),
+            TwoQubitGate::new(CZ, 1, 2),
+            TwoQubitGate::new(CX, 3, 2),
+        ];
+        gates.iter().for_each(|g| circuit.append(*g));
+
+        let batches = |b: &[&[TwoQubitGate]]| -> Vec<DPQAInstruction> {
+            b.iter()
+                .map(|g| DPQAInstruction::Gate {
+                    gates: g.to_vec(),
+                    illuminated: None,
+                })
+                .collect()
+        };
+
+        // CZ gates commute
+        let ok = batches(&[&gates[1..2], &gates[0..1], &gates[2..3]]);
+        assert_eq!(verify::<StateVector>(&circuit, &ok), Ok(()));
+        assert_eq!(verify::<Tableau>(&circuit, &ok), Ok(()));
+
+        let dropped = batches(&[&gates[0..2]]);
+        assert_eq!(
+            verify::<Tableau>(&circuit, &dropped),
+            Err(DPQAError::CircuitMismatch(String::from(
+                "CX(3, 2) was dropped 1 time(s)"
+            )))
+        );
+
+        let duplicated = batches(&[&gates[..], &gates[0..1]]);
+        assert_eq!(
+            verify::<Tableau>(&circuit, &duplicated),
+            Err(DPQAError::CircuitMismatch(String::from(
+                "CZ(0, 1) was added 1 time(s)"
+            )))
+        );
+
+        // CX(3, 2) does not commute with CZ(1, 2)
+        let reordered = batches(&[&gates[2..3], &gates[0..2]]);
+        assert!(verify::<StateVector>(&circuit, &reordered).is_err());
+        assert!(verify::<Tableau>(&circuit, &reordered).is_err());
+    }
+
+    #[test]
+    fn large_circuit() {
+        let n = 300;
+        let mut circuit = Circuit::new();
+        for ii in 0..(n - 1) {
+            circuit.append(TwoQubitGate::new(CX, ii, ii + 1));
+        }
+        let mut instructions: Vec<DPQAInstruction> = circuit
+            .iter()
+            .map(|g| DPQAInstruction::Gate {
+                gates: vec![*g],
+                illuminated: None,
+            })
+            .collect();
+        assert_eq!(verify::<Tableau>(&circuit, &instructions), Ok(()));
+        assert_eq!(
+            verify::<StateVector>(&circuit, &instructions),
+            Err(DPQAError::SimulationTooLarge {
+                n_qubits: 300,
+                max_qubits: StateVector::MAX_QUBITS
+            })
+        );
+
+        instructions.swap(100, 101);
+        assert!(verify::<Tableau>(&circuit, &instructions).is_err());
+    }
+
+    #[cfg(feature = "z3")]
+    #[test]
+    fn compiled() {
+        let mut circuit = Circuit::new();
+        circuit.append(TwoQubitGate::new(CZ, 0, 1));
+        circuit.append(TwoQubitGate::new(CX, 1, 2));
+        circuit.append(TwoQubitGate::new(CZ, 0, 3));
+        circuit.append(TwoQubitGate::new(CX, 3, 2));
+
+        let solution = DPQA::new(3, 2).solve(&circuit).unwrap();
+        assert_eq!(solution.verify(&circuit), Ok(()));
+        assert_eq!(
+            verify::<StateVector>(&circuit, solution.instructions()),
+            Ok(())
+        );
+    }
+}
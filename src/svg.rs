@@ -0,0 +1,386 @@
+use crate::{
+    dpqa::DPQA,
+    schedule::{AtomState, Schedule, Stage},
+};
+use itertools::Itertools;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Offset of the first AOD line at a site, relative to the site spacing
+const AOD_OFFSET: f64 = 0.2;
+/// Additional offset of each further AOD line stacked at the same site
+const AOD_STACK_OFFSET: f64 = 0.1;
+
+/// Contents of one frame, in pixel coordinates
+struct Frame {
+    title: String,
+    cols: Vec<f64>,
+    rows: Vec<f64>,
+    /// Position, qubit, whether the atom is in the AOD, and whether it was
+    /// just transferred
+    atoms: Vec<(f64, f64, usize, bool, bool)>,
+    /// Centers of gate pulses
+    pulses: Vec<(f64, f64)>,
+}
+
+/// Draws a compiled schedule as a sequence of SVG frames, or as a
+/// self-contained HTML animation
+pub struct SvgRenderer {
+    rows: u64,
+    cols: u64,
+    spacing: f64,
+    move_frames: usize,
+}
+
+impl SvgRenderer {
+    /// Create a renderer for the grid of the given architecture
+    pub fn new(dpqa: &DPQA) -> SvgRenderer {
+        SvgRenderer {
+            rows: dpqa.get_rows(),
+            cols: dpqa.get_cols(),
+            spacing: 60.0,
+            move_frames: 4,
+        }
+    }
+
+    /// Set the distance between neighboring sites, in pixels
+    pub fn set_spacing(&mut self, spacing: f64) {
+        self.spacing = spacing;
+    }
+
+    /// Set the number of frames used to draw each set of AOD moves (at
+    /// least one, showing the atoms after the move)
+    pub fn set_move_frames(&mut self, move_frames: usize) {
+        self.move_frames = move_frames.max(1);
+    }
+
+    fn site(&self, coord: u64) -> f64 {
+        self.spacing * (1.0 + coord as f64)
+    }
+
+    /// Pixel coordinates of AOD lines, given the grid coordinate of each.
+    /// Lines stacked at the same site are drawn side by side, in order.
+    fn line_coords(&self, lines: &BTreeMap<u64, u64>) -> BTreeMap<u64, f64> {
+        let mut stacked: BTreeMap<u64, usize> = BTreeMap::new();
+        lines
+            .iter()
+            .map(|(&line, &coord)| {
+                let k = stacked.entry(coord).or_insert(0);
+                let offset = AOD_OFFSET + AOD_STACK_OFFSET * (*k as f64);
+                *k += 1;
+                (line, self.site(coord) + self.spacing * offset)
+            })
+            .collect()
+    }
+
+    /// Pixel coordinates of the AOD lines holding atoms, using the positions
+    /// from `positions` (which may belong to a later stage)
+    fn aod_lines(
+        &self,
+        atoms: &[AtomState],
+        positions: &[AtomState],
+    ) -> (BTreeMap<u64, f64>, BTreeMap<u64, f64>) {
+        let mut cols = BTreeMap::new();
+        let mut rows = BTreeMap::new();
+        for (a, p) in atoms.iter().zip(positions) {
+            if let Some((c, r)) = a.aod {
+                cols.insert(c, p.x);
+                rows.insert(r, p.y);
+            }
+        }
+        (self.line_coords(&cols), self.line_coords(&rows))
+    }
+
+    /// Frame showing the state of a stage, with transferred atoms and
+    /// (optionally) gate pulses highlighted
+    fn stage_frame(&self, prev: Option<&Stage>, stage: &Stage, pulses: bool) -> Frame {
+        let (cols, rows) = self.aod_lines(&stage.atoms, &stage.atoms);
+        let atoms: Vec<(f64, f64, usize, bool, bool)> = stage
+            .atoms
+            .iter()
+            .enumerate()
+            .map(|(ii, a)| {
+                let transferred = prev.is_some_and(|p| p.atoms[ii].in_aod() != a.in_aod());
+                let (x, y) = match a.aod {
+                    Some((c, r)) => (cols[&c], rows[&r]),
+                    None => (self.site(a.x), self.site(a.y)),
+                };
+                (x, y, ii, a.in_aod(), transferred)
+            })
+            .collect();
+        let pulses = if pulses {
+            stage
+                .gates
+                .iter()
+                .map(|g| {
+                    let (a, b) = (&atoms[g.q_ctrl], &atoms[g.q_target]);
+                    (0.5 * (a.0 + b.0), 0.5 * (a.1 + b.1))
+                })
+                .collect()
+        } else {
+            vec![]
+        };
+        let title = match (pulses.is_empty(), prev.is_some()) {
+            (false, _) => format!("Stage {}: gates", stage.index),
+            (true, true) => format!("Stage {}: transfers", stage.index),
+            (true, false) => format!("Stage {}: initial state", stage.index),
+        };
+        Frame {
+            title,
+            cols: cols.into_values().collect(),
+            rows: rows.into_values().collect(),
+            atoms,
+            pulses,
+        }
+    }
+
+    /// Frames showing the AOD moves leading from `prev` into `stage`
+    fn move_frames(&self, prev: &Stage, stage: &Stage) -> Vec<Frame> {
+        let (cols_from, rows_from) = self.aod_lines(&prev.atoms, &prev.atoms);
+        let (cols_to, rows_to) = self.aod_lines(&prev.atoms, &stage.atoms);
+        let lerp = |a: f64, b: f64, f: f64| a + (b - a) * f;
+
+        (1..=self.move_frames)
+            .map(|k| {
+                let f = k as f64 / self.move_frames as f64;
+                let cols: BTreeMap<u64, f64> = cols_from
+                    .iter()
+                    .map(|(c, &x)| (*c, lerp(x, cols_to[c], f)))
+                    .collect();
+                let rows: BTreeMap<u64, f64> = rows_from
+                    .iter()
+                    .map(|(r, &y)| (*r, lerp(y, rows_to[r], f)))
+                    .collect();
+                let atoms = prev
+                    .atoms
+                    .iter()
+                    .enumerate()
+                    .map(|(ii, a)| match a.aod {
+                        Some((c, r)) => (cols[&c], rows[&r], ii, true, false),
+                        None => (self.site(a.x), self.site(a.y), ii, false, false),
+                    })
+                    .collect();
+                Frame {
+                    title: format!("Stage {}: move {}/{}", stage.index, k, self.move_frames),
+                    cols: cols.into_values().collect(),
+                    rows: rows.into_values().collect(),
+                    atoms,
+                    pulses: vec![],
+                }
+            })
+            .collect()
+    }
+
+    fn draw(&self, frame: &Frame) -> String {
+        let sp = self.spacing;
+        let width = sp * (1.0 + self.cols as f64);
+        let height = sp * (1.0 + self.rows as f64);
+        let mut svg = String::new();
+
+        // Writing to a String cannot fail
+        let _ = writeln!(
+            svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w:.0}" height="{h:.0}" viewBox="0 0 {w:.1} {h:.1}">"#,
+            w = width,
+            h = height
+        );
+        let _ = writeln!(
+            svg,
+            r##"<rect width="100%" height="100%" fill="#ffffff"/>"##
+        );
+        let _ = writeln!(
+            svg,
+            r#"<text x="{:.1}" y="{:.1}" font-family="sans-serif" font-size="{:.1}">{}</text>"#,
+            0.2 * sp,
+            0.5 * sp,
+            0.3 * sp,
+            frame.title
+        );
+
+        // SLM traps
+        for (x, y) in (0..self.cols).cartesian_product(0..self.rows) {
+            let _ = writeln!(
+                svg,
+                r##"<circle cx="{:.1}" cy="{:.1}" r="{:.1}" fill="none" stroke="#999999"/>"##,
+                self.site(x),
+                self.site(y),
+                0.15 * sp
+            );
+        }
+
+        // AOD lines
+        for x in &frame.cols {
+            let _ = writeln!(
+                svg,
+                r##"<line x1="{x:.1}" y1="{:.1}" x2="{x:.1}" y2="{:.1}" stroke="#d62728" stroke-dasharray="4 4"/>"##,
+                0.7 * sp,
+                height - 0.3 * sp,
+                x = x
+            );
+        }
+        for y in &frame.rows {
+            let _ = writeln!(
+                svg,
+                r##"<line x1="{:.1}" y1="{y:.1}" x2="{:.1}" y2="{y:.1}" stroke="#d62728" stroke-dasharray="4 4"/>"##,
+                0.7 * sp,
+                width - 0.3 * sp,
+                y = y
+            );
+        }
+
+        // Gate pulses
+        for (x, y) in &frame.pulses {
+            let _ = writeln!(
+                svg,
+                r##"<circle cx="{:.1}" cy="{:.1}" r="{:.1}" fill="#9467bd" fill-opacity="0.3"/>"##,
+                x,
+                y,
+                0.45 * sp
+            );
+        }
+
+        // Atoms
+        for &(x, y, qubit, in_aod, transferred) in &frame.atoms {
+            let fill = if in_aod { "#ff7f0e" } else { "#1f77b4" };
+            let stroke = if transferred {
+                r##" stroke="#2ca02c" stroke-width="3""##
+            } else {
+                ""
+            };
+            let _ = writeln!(
+                svg,
+                r#"<circle cx="{:.1}" cy="{:.1}" r="{:.1}" fill="{}"{}/>"#,
+                x,
+                y,
+                0.12 * sp,
+                fill,
+                stroke
+            );
+            let _ = writeln!(
+                svg,
+                r##"<text x="{:.1}" y="{:.1}" font-family="sans-serif" font-size="{:.1}" fill="#ffffff" text-anchor="middle" dominant-baseline="central">{}</text>"##,
+                x,
+                y,
+                0.15 * sp,
+                qubit
+            );
+        }
+        svg += "</svg>\n";
+        svg
+    }
+
+    /// Draw the schedule as a list of SVG documents. Each stage is drawn as
+    /// the AOD moves leading into it (interpolated over several frames), the
+    /// state after the transfers, and the gate pulses if there are any.
+    pub fn frames(&self, schedule: &Schedule) -> Vec<String> {
+        let mut frames = vec![];
+        let mut prev: Option<&Stage> = None;
+        for stage in schedule.iter() {
+            if let Some(p) = prev {
+                if !stage.moves.is_empty() {
+                    frames.extend(self.move_frames(p, stage));
+                }
+            }
+            frames.push(self.stage_frame(prev, stage, false));
+            if !stage.gates.is_empty() {
+                frames.push(self.stage_frame(prev, stage, true));
+            }
+            prev = Some(stage);
+        }
+        frames.iter().map(|f| self.draw(f)).collect()
+    }
+
+    /// Draw the schedule as a self-contained HTML page that steps through
+    /// the frames, without loading any external resources
+    pub fn html(&self, schedule: &Schedule) -> String {
+        let frames = self.frames(schedule);
+        let mut html = String::from(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+             <title>DPQA schedule</title>\n\
+             <style>.frame { display: none; } .frame.shown { display: block; }</style>\n\
+             </head>\n<body>\n\
+             <button id=\"play\">Pause</button> <span id=\"counter\"></span>\n",
+        );
+        for (ii, frame) in frames.iter().enumerate() {
+            let class = if ii == 0 { "frame shown" } else { "frame" };
+            let _ = write!(html, "<div class=\"{}\">\n{}</div>\n", class, frame);
+        }
+        html += "<script>\n\
+            const frames = document.querySelectorAll('.frame');\n\
+            const counter = document.getElementById('counter');\n\
+            const button = document.getElementById('play');\n\
+            let current = 0;\n\
+            let playing = true;\n\
+            function show(n) {\n\
+            \x20 frames[current].classList.remove('shown');\n\
+            \x20 current = n % frames.length;\n\
+            \x20 frames[current].classList.add('shown');\n\
+            \x20 counter.textContent = (current + 1) + ' / ' + frames.length;\n\
+            }\n\
+            button.onclick = () => {\n\
+            \x20 playing = !playing;\n\
+            \x20 button.textContent = playing ? 'Pause' : 'Play';\n\
+            };\n\
+            show(0);\n\
+            setInterval(() => { if (playing) show(current + 1); }, 500);\n\
+            </script>\n</body>\n</html>\n";
+        html
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SvgRenderer;
+    use crate::circuit::Circuit;
+    use crate::dpqa::Addressing;
+    use crate::dpqa::DPQA;
+    use crate::gates::TwoQubitGate;
+    use crate::gates::TwoQubitGateType::CZ;
+    use crate::schedule::{DPQAVarsValues, Schedule};
+
+    fn schedule() -> Schedule {
+        let mut circuit = Circuit::new();
+        circuit.append(TwoQubitGate::new(CZ, 0, 1));
+        circuit.append(TwoQubitGate::new(CZ, 0, 2));
+
+        let vals = DPQAVarsValues {
+            xy: vec![
+                vec![(0, 0), (1, 0)],
+                vec![(0, 0), (0, 0)],
+                vec![(1, 0), (1, 0)],
+            ],
+            cr: vec![vec![(0, 0), (0, 0)]; 3],
+            aod: vec![vec![true, true], vec![false, false], vec![false, false]],
+            t: vec![0, 1],
+            t_ops: vec![],
+        };
+        Schedule::from_values(&circuit, &vals, &Addressing::Global).unwrap()
+    }
+
+    #[test]
+    fn frames() {
+        let mut renderer = SvgRenderer::new(&DPQA::new(2, 2));
+        let frames = renderer.frames(&schedule());
+        // Stage 0: initial state and gates, stage 1: moves, transfers, gates
+        assert_eq!(frames.len(), 8);
+        assert!(frames
+            .iter()
+            .all(|f| f.starts_with("<svg") && f.ends_with("</svg>\n")));
+
+        // AOD column 0 moves from x=0 to x=1, so it is halfway across in the
+        // first of two move frames
+        renderer.set_move_frames(2);
+        let frames = renderer.frames(&schedule());
+        assert_eq!(frames.len(), 6);
+        assert!(frames[2].contains("Stage 1: move 1/2"));
+        assert!(frames[2].contains(r#"<line x1="102.0""#));
+        assert!(frames[3].contains(r#"<line x1="132.0""#));
+    }
+
+    #[test]
+    fn html() {
+        let html = SvgRenderer::new(&DPQA::new(2, 2)).html(&schedule());
+        assert_eq!(html.matches("<svg").count(), 8);
+        assert!(!html.contains("src=") && !html.contains("href="));
+    }
+}
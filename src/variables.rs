@@ -1,47 +1,60 @@
-use crate::circuit::Circuit;
+use crate::{
+    backend::{ConstraintSink, CountingSink, LexOptimize, ModelValue},
+    circuit::Circuit,
+    dpqa::{Addressing, FinalLayout, DPQA},
+    encoding::{Coord, Domain},
+    error::DPQAError,
+    gates::{QubitOp, TwoQubitGate},
+    schedule::{AtomState, DPQAVarsValues, Schedule},
+    sequencing::AOD_MAX_STACK,
+};
 use itertools::{izip, Itertools};
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
 use z3::{
     ast::{self, Ast},
-    Context, Optimize,
+    Context, Model, SatResult,
 };
 
 /// Variables associated with a qubit
 struct QubitVars<'ctx> {
     // Positions
-    pub x: Vec<ast::Int<'ctx>>,
-    pub y: Vec<ast::Int<'ctx>>,
-    pub c: Vec<ast::Int<'ctx>>,
-    pub r: Vec<ast::Int<'ctx>>,
+    pub x: Vec<Coord<'ctx>>,
+    pub y: Vec<Coord<'ctx>>,
+    pub c: Vec<Coord<'ctx>>,
+    pub r: Vec<Coord<'ctx>>,
 
     // Determines whether qubit is in SLM (false) or AOD (true)
     pub aod: Vec<ast::Bool<'ctx>>,
 }
 
 /// Variables associated with a qubit at a given stage
-struct QubitVarsStage<'ctx> {
-    x: &'ctx ast::Int<'ctx>,
-    y: &'ctx ast::Int<'ctx>,
-    c: &'ctx ast::Int<'ctx>,
-    r: &'ctx ast::Int<'ctx>,
-    aod: &'ctx ast::Bool<'ctx>,
+struct QubitVarsStage<'a, 'ctx> {
+    x: &'a Coord<'ctx>,
+    y: &'a Coord<'ctx>,
+    c: &'a Coord<'ctx>,
+    r: &'a Coord<'ctx>,
+    aod: &'a ast::Bool<'ctx>,
 }
 
 impl<'ctx> QubitVars<'ctx> {
-    pub fn new(context: &'ctx Context, qubit_idx: usize, n_stages: usize) -> QubitVars<'ctx> {
-        let create_int_vars = |var_name: &str| -> Vec<ast::Int<'ctx>> {
+    pub fn new(
+        context: &'ctx Context,
+        domains: &[Domain<'ctx>; 4],
+        qubit_idx: usize,
+        n_stages: usize,
+    ) -> QubitVars<'ctx> {
+        let create_vars = |var_name: &str, domain: &Domain<'ctx>| -> Vec<Coord<'ctx>> {
             (0..n_stages)
-                .map(|jj| {
-                    ast::Int::new_const(context, format!("{}_q{}_t{}", var_name, qubit_idx, jj))
-                })
+                .map(|jj| domain.var(format!("{}_q{}_t{}", var_name, qubit_idx, jj)))
                 .collect()
         };
 
         QubitVars {
-            x: create_int_vars("x"),
-            y: create_int_vars("y"),
-            c: create_int_vars("c"),
-            r: create_int_vars("r"),
+            x: create_vars("x", &domains[0]),
+            y: create_vars("y", &domains[1]),
+            c: create_vars("c", &domains[2]),
+            r: create_vars("r", &domains[3]),
             aod: (0..n_stages)
                 .map(|jj| {
                     ast::Bool::new_const(context, format!("{}_q{}_t{}", "aod", qubit_idx, jj))
@@ -50,7 +63,7 @@ impl<'ctx> QubitVars<'ctx> {
         }
     }
 
-    fn iter(&self) -> impl Iterator<Item = QubitVarsStage<'_>> {
+    fn iter(&self) -> impl Iterator<Item = QubitVarsStage<'_, 'ctx>> {
         izip!(&self.x, &self.y, &self.c, &self.r, &self.aod)
             .map(|(x, y, c, r, aod)| QubitVarsStage { x, y, c, r, aod })
     }
@@ -62,90 +75,120 @@ pub struct DPQAVars<'ctx, 'circ> {
     zero: ast::Int<'ctx>,
     one: ast::Int<'ctx>,
 
-    // Grid bounds
-    x_max: ast::Int<'ctx>,
-    y_max: ast::Int<'ctx>,
-    c_max: ast::Int<'ctx>,
-    r_max: ast::Int<'ctx>,
+    // Ranges of the positions (x, y), AOD indices (c, r) and gate stages
+    x_domain: Domain<'ctx>,
+    y_domain: Domain<'ctx>,
+    c_domain: Domain<'ctx>,
+    r_domain: Domain<'ctx>,
+    t_domain: Domain<'ctx>,
+
+    // Minimum distance between illuminated atoms not in the same gate
+    interaction_radius: u64,
+    addressing: Addressing,
 
     // Qubit variables
     qubits: Vec<QubitVars<'ctx>>,
 
     // Time when each gate is executed
-    t: Vec<ast::Int<'ctx>>,
-    t_max: ast::Int<'ctx>,
+    t: Vec<Coord<'ctx>>,
     t_order: Vec<(usize, usize)>,
-    s_vals: Vec<ast::Int<'ctx>>,
-}
+    // Stage at which a gate must run to be executed at each qubit stage.
+    // With a cyclic schedule, the extra last stage stands for stage 0 of the
+    // next repetition. Return stages at the end have labels no gate can
+    // take.
+    s_vals: Vec<Coord<'ctx>>,
+    cyclic: bool,
+    final_layout: FinalLayout,
+    n_return_stages: usize,
 
-/// Results from a successful solver run
-pub struct DPQAVarsValues {
-    pub xy: Vec<Vec<(u64, u64)>>,
-    pub cr: Vec<Vec<(u64, u64)>>,
-    pub aod: Vec<Vec<bool>>,
-    pub t: Vec<u64>,
+    // Stage after whose gates each measurement or reset is executed
+    t_ops: Vec<Coord<'ctx>>,
+    readout_zone: Option<Vec<u64>>,
 }
 
 impl<'ctx, 'circ> DPQAVars<'ctx, 'circ> {
     pub fn new(
         context: &'ctx Context,
         circuit: &'circ Circuit,
-        rows: u64,
-        cols: u64,
-        aod_rows: u64,
-        aod_cols: u64,
+        dpqa: &DPQA,
         n_stages: usize,
+        n_return_stages: usize,
     ) -> DPQAVars<'ctx, 'circ> {
         let n_qubits = circuit.get_n_qubits();
         let n_gates = circuit.get_n_two_qubit_gates();
+        let cyclic = dpqa.is_cyclic();
+        let n_qubit_stages = if cyclic { n_stages + 1 } else { n_stages } + n_return_stages;
+        let encoding = dpqa.get_encoding();
+        let domains = [
+            Domain::new(context, encoding, dpqa.get_cols()),
+            Domain::new(context, encoding, dpqa.get_rows()),
+            Domain::new(context, encoding, dpqa.get_aod_cols()),
+            Domain::new(context, encoding, dpqa.get_aod_rows()),
+        ];
+        let t_domain = Domain::new(context, encoding, n_stages as u64);
 
         DPQAVars {
-            circuit: circuit,
-            zero: ast::Int::from_u64(&context, 0),
-            one: ast::Int::from_u64(&context, 1),
-            x_max: ast::Int::from_u64(&context, cols),
-            y_max: ast::Int::from_u64(&context, rows),
-            c_max: ast::Int::from_u64(&context, aod_cols),
-            r_max: ast::Int::from_u64(&context, aod_rows),
+            circuit,
+            zero: ast::Int::from_u64(context, 0),
+            one: ast::Int::from_u64(context, 1),
+            x_domain: domains[0],
+            y_domain: domains[1],
+            c_domain: domains[2],
+            r_domain: domains[3],
+            t_domain,
+            interaction_radius: dpqa.get_interaction_radius(),
+            addressing: dpqa.get_addressing().clone(),
             qubits: (0..n_qubits)
-                .map(|ii| QubitVars::new(&context, ii, n_stages))
+                .map(|ii| QubitVars::new(context, &domains, ii, n_qubit_stages))
                 .collect(),
             t: (0..n_gates)
-                .map(|ii| ast::Int::new_const(context, format!("t_{}", ii)))
+                .map(|ii| t_domain.var(format!("t_{}", ii)))
                 .collect(),
-            t_max: ast::Int::from_u64(&context, n_stages as u64),
             t_order: circuit.get_gate_ordering(),
-            s_vals: (0..n_stages)
-                .map(|ii| ast::Int::from_u64(&context, ii as u64))
+            s_vals: (0..n_qubit_stages)
+                .map(|ii| {
+                    let label = if cyclic && ii == n_stages { 0 } else { ii };
+                    t_domain.constant(label as u64)
+                })
                 .collect(),
+            cyclic,
+            final_layout: dpqa.get_final_layout().clone(),
+            n_return_stages,
+            t_ops: (0..circuit.get_n_ops())
+                .map(|ii| t_domain.var(format!("t_op_{}", ii)))
+                .collect(),
+            readout_zone: dpqa.get_readout_zone().map(|rows| rows.to_vec()),
         }
     }
 
     /// Constrain all qubits to stay within grid bounds
-    fn constraint_grid_bounds(&self, solver: &Optimize) {
-        let set_bounds = |vars: &[ast::Int], lower_bound: &ast::Int, upper_bound: &ast::Int| {
+    fn constraint_grid_bounds(&self, solver: &impl ConstraintSink<'ctx>) {
+        let set_bounds = |vars: &[Coord<'ctx>], domain: &Domain<'ctx>| {
             for v in vars {
-                let lb = v.ge(&lower_bound);
-                solver.assert(&lb);
-                let ub = v.lt(&upper_bound);
-                solver.assert(&ub);
+                for bound in domain.bounds(v) {
+                    solver.assert(&bound);
+                }
             }
         };
 
         for q in &self.qubits {
-            set_bounds(&q.x, &self.zero, &self.x_max);
-            set_bounds(&q.y, &self.zero, &self.y_max);
-            set_bounds(&q.c, &self.zero, &self.c_max);
-            set_bounds(&q.r, &self.zero, &self.r_max);
+            set_bounds(&q.x, &self.x_domain);
+            set_bounds(&q.y, &self.y_domain);
+            set_bounds(&q.c, &self.c_domain);
+            set_bounds(&q.r, &self.r_domain);
         }
     }
 
-    fn require_unchanged(solver: &Optimize, condition: &ast::Bool, var: &[ast::Int]) {
+    fn require_unchanged(
+        solver: &impl ConstraintSink<'ctx>,
+        condition: &ast::Bool<'ctx>,
+        var: &[Coord<'ctx>],
+    ) {
         solver.assert(&condition.implies(&var[0]._eq(&var[1])));
     }
 
     /// Any qubit in an SLM trap must stay in place between stages
-    fn constraint_fixed_slm(&self, solver: &Optimize) {
+    fn constraint_fixed_slm(&self, solver: &impl ConstraintSink<'ctx>) {
         for q in &self.qubits {
             // Loop over stages
             for (x_step, y_step, aod) in izip!(q.x.windows(2), q.y.windows(2), &q.aod) {
@@ -156,12 +199,12 @@ impl<'ctx, 'circ> DPQAVars<'ctx, 'circ> {
     }
 
     /// Rows and columns of the AOD grid must move together
-    fn constraint_aod_move_together(&self, solver: &Optimize) {
+    fn constraint_aod_move_together(&self, solver: &impl ConstraintSink<'ctx>) {
         for q in &self.qubits {
             // Loop over stages
             for (c_step, r_step, aod) in izip!(q.c.windows(2), q.r.windows(2), &q.aod) {
-                DPQAVars::require_unchanged(solver, &aod, c_step);
-                DPQAVars::require_unchanged(solver, &aod, r_step);
+                DPQAVars::require_unchanged(solver, aod, c_step);
+                DPQAVars::require_unchanged(solver, aod, r_step);
             }
         }
 
@@ -170,20 +213,22 @@ impl<'ctx, 'circ> DPQAVars<'ctx, 'circ> {
         // at the same value of y), and similarly for columns.
         let context = solver.get_context();
         let cr_eq_implies_xy_eq =
-            |start_aod: &ast::Bool, cr: (&ast::Int, &ast::Int), xy: (&ast::Int, &ast::Int)| {
-                let cr_eq = cr.0._eq(&cr.1);
+            |start_aod: &ast::Bool<'ctx>,
+             cr: (&Coord<'ctx>, &Coord<'ctx>),
+             xy: (&Coord<'ctx>, &Coord<'ctx>)| {
+                let cr_eq = cr.0._eq(cr.1);
                 let cr_eq_aod = ast::Bool::and(context, &[start_aod, &cr_eq]);
-                let xy_eq = xy.0._eq(&xy.1);
+                let xy_eq = xy.0._eq(xy.1);
                 solver.assert(&cr_eq_aod.implies(&xy_eq));
             };
 
         // Loop over pairs of distinct qubits
         for (q0, q1) in self.qubits.iter().tuple_combinations() {
-            let stages: Vec<(QubitVarsStage<'_>, QubitVarsStage<'_>)> =
+            let stages: Vec<(QubitVarsStage<'_, 'ctx>, QubitVarsStage<'_, 'ctx>)> =
                 izip!(q0.iter(), q1.iter()).collect();
             for stage_pair in stages.windows(2) {
                 let (curr, next) = (&stage_pair[0], &stage_pair[1]);
-                let both_aod = ast::Bool::and(context, &[&curr.0.aod, &curr.1.aod]);
+                let both_aod = ast::Bool::and(context, &[curr.0.aod, curr.1.aod]);
                 cr_eq_implies_xy_eq(&both_aod, (curr.0.c, curr.1.c), (next.0.x, next.1.x));
                 cr_eq_implies_xy_eq(&both_aod, (curr.0.r, curr.1.r), (next.0.y, next.1.y));
             }
@@ -192,19 +237,21 @@ impl<'ctx, 'circ> DPQAVars<'ctx, 'circ> {
 
     /// The order of AOD columns must be consistent with the order
     /// of SLM columns
-    fn constraint_aod_order_from_slm(&self, solver: &Optimize) {
+    fn constraint_aod_order_from_slm(&self, solver: &impl ConstraintSink<'ctx>) {
         let context = solver.get_context();
         let xy_lt_implies_cr_lt =
-            |aod: &ast::Bool, xy: (&ast::Int, &ast::Int), cr: (&ast::Int, &ast::Int)| {
-                let xy_lt = xy.0.lt(&xy.1);
+            |aod: &ast::Bool<'ctx>,
+             xy: (&Coord<'ctx>, &Coord<'ctx>),
+             cr: (&Coord<'ctx>, &Coord<'ctx>)| {
+                let xy_lt = xy.0.lt(xy.1);
                 let enforce_lt = ast::Bool::and(context, &[aod, &xy_lt]);
-                let cr_lt = cr.0.lt(&cr.1);
+                let cr_lt = cr.0.lt(cr.1);
                 solver.assert(&enforce_lt.implies(&cr_lt));
             };
 
         for (q0, q1) in self.qubits.iter().tuple_combinations() {
             for vars in izip!(q0.iter(), q1.iter()) {
-                let both_aod = ast::Bool::and(context, &[&vars.0.aod, &vars.1.aod]);
+                let both_aod = ast::Bool::and(context, &[vars.0.aod, vars.1.aod]);
 
                 xy_lt_implies_cr_lt(&both_aod, (vars.0.x, vars.1.x), (vars.0.c, vars.1.c));
                 xy_lt_implies_cr_lt(&both_aod, (vars.1.x, vars.0.x), (vars.1.c, vars.0.c));
@@ -217,22 +264,24 @@ impl<'ctx, 'circ> DPQAVars<'ctx, 'circ> {
 
     /// The order of SLM columns must be consistent with the order
     /// of AOD columns
-    fn constraint_slm_order_from_aod(&self, solver: &Optimize) {
+    fn constraint_slm_order_from_aod(&self, solver: &impl ConstraintSink<'ctx>) {
         let context = solver.get_context();
         let cr_lt_implies_xy_le =
-            |aod: &ast::Bool, cr: (&ast::Int, &ast::Int), xy: (&ast::Int, &ast::Int)| {
-                let cr_lt = cr.0.lt(&cr.1);
-                let enforce_le = ast::Bool::and(context, &[&aod, &cr_lt]);
-                let xy_le = xy.0.le(&xy.1);
+            |aod: &ast::Bool<'ctx>,
+             cr: (&Coord<'ctx>, &Coord<'ctx>),
+             xy: (&Coord<'ctx>, &Coord<'ctx>)| {
+                let cr_lt = cr.0.lt(cr.1);
+                let enforce_le = ast::Bool::and(context, &[aod, &cr_lt]);
+                let xy_le = xy.0.le(xy.1);
                 solver.assert(&enforce_le.implies(&xy_le));
             };
 
         for (q0, q1) in self.qubits.iter().tuple_combinations() {
-            let stages: Vec<(QubitVarsStage<'_>, QubitVarsStage<'_>)> =
+            let stages: Vec<(QubitVarsStage<'_, 'ctx>, QubitVarsStage<'_, 'ctx>)> =
                 izip!(q0.iter(), q1.iter()).collect();
             for stage_pair in stages.windows(2) {
                 let (curr, next) = (&stage_pair[0], &stage_pair[1]);
-                let both_aod = ast::Bool::and(context, &[&curr.0.aod, &curr.1.aod]);
+                let both_aod = ast::Bool::and(context, &[curr.0.aod, curr.1.aod]);
 
                 cr_lt_implies_xy_le(&both_aod, (curr.0.c, curr.1.c), (next.0.x, next.1.x));
                 cr_lt_implies_xy_le(&both_aod, (curr.1.c, curr.0.c), (next.1.x, next.0.x));
@@ -244,15 +293,16 @@ impl<'ctx, 'circ> DPQAVars<'ctx, 'circ> {
     }
 
     /// Prevent stacking/crowding of more than 3 AOD rows/columns
-    fn constraint_aod_crowding(&self, solver: &Optimize) {
+    fn constraint_aod_crowding(&self, solver: &impl ConstraintSink<'ctx>) {
         let context = solver.get_context();
-        let max_stack = ast::Int::from_u64(&context, 3);
 
         let cr_diff_implies_xy_gt =
-            |aod: &ast::Bool, cr: (&ast::Int, &ast::Int), xy: (&ast::Int, &ast::Int)| {
-                let cr_diff = ast::Int::sub(context, &[cr.0, cr.1]).ge(&max_stack);
-                let enforce_gt = ast::Bool::and(context, &[&aod, &cr_diff]);
-                let xy_gt = xy.0.gt(&xy.1);
+            |aod: &ast::Bool<'ctx>,
+             cr: (&Coord<'ctx>, &Coord<'ctx>),
+             xy: (&Coord<'ctx>, &Coord<'ctx>)| {
+                let cr_diff = cr.0.diff_ge(cr.1, AOD_MAX_STACK);
+                let enforce_gt = ast::Bool::and(context, &[aod, &cr_diff]);
+                let xy_gt = xy.0.gt(xy.1);
                 solver.assert(&enforce_gt.implies(&xy_gt));
             };
 
@@ -266,11 +316,11 @@ impl<'ctx, 'circ> DPQAVars<'ctx, 'circ> {
                 cr_diff_implies_xy_gt(&both_aod, (&q1.r[0], &q0.r[0]), (&q1.y[0], &q0.y[0]));
             }
 
-            let stages: Vec<(QubitVarsStage<'_>, QubitVarsStage<'_>)> =
+            let stages: Vec<(QubitVarsStage<'_, 'ctx>, QubitVarsStage<'_, 'ctx>)> =
                 izip!(q0.iter(), q1.iter()).collect();
             for stage_pair in stages.windows(2) {
                 let (curr, next) = (&stage_pair[0], &stage_pair[1]);
-                let both_aod = ast::Bool::and(context, &[&curr.0.aod, &curr.1.aod]);
+                let both_aod = ast::Bool::and(context, &[curr.0.aod, curr.1.aod]);
                 cr_diff_implies_xy_gt(&both_aod, (curr.0.c, curr.1.c), (next.0.x, next.1.x));
                 cr_diff_implies_xy_gt(&both_aod, (curr.0.r, curr.1.r), (next.0.y, next.1.y));
                 cr_diff_implies_xy_gt(&both_aod, (curr.1.c, curr.0.c), (next.1.x, next.0.x));
@@ -280,7 +330,7 @@ impl<'ctx, 'circ> DPQAVars<'ctx, 'circ> {
     }
 
     /// Limit traps to one atom at a time
-    fn constraint_site_crowding(&self, solver: &Optimize) {
+    fn constraint_site_crowding(&self, solver: &impl ConstraintSink<'ctx>) {
         let context = solver.get_context();
 
         for (q0, q1) in self.qubits.iter().tuple_combinations() {
@@ -299,11 +349,11 @@ impl<'ctx, 'circ> DPQAVars<'ctx, 'circ> {
     }
 
     /// Only allow AOD-SLM transfer when there is one atom at a given site
-    fn constraint_no_swap(&self, solver: &Optimize) {
+    fn constraint_no_swap(&self, solver: &impl ConstraintSink<'ctx>) {
         let context = solver.get_context();
 
         for (q0, q1) in self.qubits.iter().tuple_combinations() {
-            let stages: Vec<(QubitVarsStage<'_>, QubitVarsStage<'_>)> =
+            let stages: Vec<(QubitVarsStage<'_, 'ctx>, QubitVarsStage<'_, 'ctx>)> =
                 izip!(q0.iter(), q1.iter()).collect();
             for stage_pair in stages.windows(2) {
                 let (curr, next) = (&stage_pair[0], &stage_pair[1]);
@@ -311,7 +361,7 @@ impl<'ctx, 'circ> DPQAVars<'ctx, 'circ> {
                     ast::Bool::and(context, &[&next.0.x._eq(next.1.x), &next.0.y._eq(next.1.y)]);
                 let no_swap = ast::Bool::and(
                     context,
-                    &[&curr.0.aod._eq(&next.0.aod), &curr.1.aod._eq(&next.1.aod)],
+                    &[&curr.0.aod._eq(next.0.aod), &curr.1.aod._eq(next.1.aod)],
                 );
                 solver.assert(&same_site.implies(&no_swap));
             }
@@ -320,10 +370,11 @@ impl<'ctx, 'circ> DPQAVars<'ctx, 'circ> {
 
     /// Restrict each gate time to 0 <= t < self.n_stages, and ensure that
     /// gates with dependencies on each other are run in the right order
-    pub fn constraint_t_bounds(&self, solver: &Optimize) {
+    pub fn constraint_t_bounds(&self, solver: &impl ConstraintSink<'ctx>) {
         for t_var in &self.t {
-            solver.assert(&t_var.ge(&self.zero));
-            solver.assert(&t_var.lt(&self.t_max));
+            for bound in self.t_domain.bounds(t_var) {
+                solver.assert(&bound);
+            }
         }
 
         for &(g0, g1) in &self.t_order {
@@ -331,62 +382,525 @@ impl<'ctx, 'circ> DPQAVars<'ctx, 'circ> {
         }
     }
 
+    /// Measurements and resets run after the gates of their stage, after
+    /// any earlier gates and before any later gates on the same qubit
+    fn constraint_ops_timing(&self, solver: &impl ConstraintSink<'ctx>) {
+        for t_var in &self.t_ops {
+            for bound in self.t_domain.bounds(t_var) {
+                solver.assert(&bound);
+            }
+        }
+
+        for (t_op, (pos, op)) in self.t_ops.iter().zip(self.circuit.ops()) {
+            let q = op.qubit();
+            for (ii, g) in self.circuit.iter().enumerate() {
+                if g.q_ctrl != q && g.q_target != q {
+                    continue;
+                }
+                if ii < *pos {
+                    solver.assert(&self.t[ii].le(t_op));
+                } else {
+                    solver.assert(&t_op.lt(&self.t[ii]));
+                }
+            }
+        }
+
+        // Operations on the same qubit stay in order
+        let ops: Vec<_> = self.t_ops.iter().zip(self.circuit.ops()).collect();
+        for ((t0, (_, op0)), (t1, (_, op1))) in ops.iter().tuple_combinations() {
+            if op0.qubit() == op1.qubit() {
+                solver.assert(&t0.le(t1));
+            }
+        }
+    }
+
+    /// If a readout zone is set, measured atoms must be inside it, and all
+    /// other atoms outside it, at the stage of the measurement
+    fn constraint_readout_zone(&self, solver: &impl ConstraintSink<'ctx>) {
+        let zone = match &self.readout_zone {
+            Some(zone) => zone,
+            None => return,
+        };
+        let context = solver.get_context();
+        let zone_rows: Vec<Coord> = zone
+            .iter()
+            .map(|&row| self.y_domain.constant(row))
+            .collect();
+
+        let mut measured: Vec<Vec<usize>> = vec![vec![]; self.qubits.len()];
+        for (ii, (_, op)) in self.circuit.ops().enumerate() {
+            if let QubitOp::Measure { qubit, .. } = op {
+                measured[*qubit].push(ii);
+            }
+        }
+        let all_measurements: Vec<usize> = measured.iter().flatten().copied().collect();
+        if all_measurements.is_empty() {
+            return;
+        }
+
+        for (q, op_indices) in self.qubits.iter().zip(&measured) {
+            for (y, stage) in izip!(&q.y, &self.s_vals) {
+                let in_zone_args: Vec<ast::Bool> = zone_rows.iter().map(|r| y._eq(r)).collect();
+                let in_zone = ast::Bool::or(
+                    context,
+                    &in_zone_args.iter().collect::<Vec<&ast::Bool<'ctx>>>(),
+                );
+                let readout = self.op_at(&all_measurements, stage);
+                if op_indices.is_empty() {
+                    solver.assert(&readout.implies(&in_zone.not()));
+                } else {
+                    let is_measured = self.op_at(op_indices, stage);
+                    solver.assert(&is_measured.implies(&in_zone));
+                    let shielded = ast::Bool::and(context, &[&readout, &is_measured.not()]);
+                    solver.assert(&shielded.implies(&in_zone.not()));
+                }
+            }
+        }
+    }
+
+    /// For a cyclic schedule, the extra last stage must return every atom to
+    /// its initial position and trap
+    fn constraint_cyclic(&self, solver: &impl ConstraintSink<'ctx>) {
+        if !self.cyclic {
+            return;
+        }
+        for q in &self.qubits {
+            let last = q.x.len() - 1;
+            solver.assert(&q.x[last]._eq(&q.x[0]));
+            solver.assert(&q.y[last]._eq(&q.y[0]));
+            solver.assert(&q.aod[last]._eq(&q.aod[0]));
+            solver.assert(&q.aod[0].implies(&q.c[last]._eq(&q.c[0])));
+            solver.assert(&q.aod[0].implies(&q.r[last]._eq(&q.r[0])));
+        }
+    }
+
+    /// The last stage must match the final layout
+    fn constraint_final_layout(&self, solver: &impl ConstraintSink<'ctx>) {
+        for (ii, q) in self.qubits.iter().enumerate() {
+            let last = q.x.len() - 1;
+            match &self.final_layout {
+                FinalLayout::Any => return,
+                FinalLayout::AllSLM => solver.assert(&q.aod[last].not()),
+                FinalLayout::Initial => {
+                    solver.assert(&q.x[last]._eq(&q.x[0]));
+                    solver.assert(&q.y[last]._eq(&q.y[0]));
+                    solver.assert(&q.aod[last]._eq(&q.aod[0]));
+                    solver.assert(&q.aod[0].implies(&q.c[last]._eq(&q.c[0])));
+                    solver.assert(&q.aod[0].implies(&q.r[last]._eq(&q.r[0])));
+                }
+                FinalLayout::Custom(sites) => {
+                    let (x, y) = sites[ii];
+                    solver.assert(&q.aod[last].not());
+                    solver.assert(&q.x[last]._eq(&self.x_domain.constant(x)));
+                    solver.assert(&q.y[last]._eq(&self.y_domain.constant(y)));
+                }
+            }
+        }
+    }
+
+    /// Every qubit must start on one of the given atoms, and no two qubits
+    /// on the same atom
+    pub fn constraint_loaded_atoms(
+        &self,
+        solver: &impl ConstraintSink<'ctx>,
+        loaded: &[AtomState],
+    ) {
+        let context = self.zero.get_ctx();
+        let on_atom: Vec<Vec<ast::Bool>> = self
+            .qubits
+            .iter()
+            .map(|q| {
+                loaded
+                    .iter()
+                    .map(|a| {
+                        ast::Bool::and(
+                            context,
+                            &[
+                                &q.x[0]._eq(&self.x_domain.constant(a.x)),
+                                &q.y[0]._eq(&self.y_domain.constant(a.y)),
+                                &q.aod[0]._eq(&ast::Bool::from_bool(context, a.in_aod())),
+                            ],
+                        )
+                    })
+                    .collect()
+            })
+            .collect();
+
+        for atoms in &on_atom {
+            solver.assert(&ast::Bool::or(context, &atoms.iter().collect::<Vec<_>>()));
+        }
+        for (atoms0, atoms1) in on_atom.iter().tuple_combinations() {
+            for (a0, a1) in atoms0.iter().zip(atoms1) {
+                solver.assert(&ast::Bool::and(context, &[a0, a1]).not());
+            }
+        }
+    }
+
+    /// Prefer to keep the trajectories of the given qubits from a previous
+    /// schedule. Returns one literal per qubit, which is true if the qubit
+    /// keeps its trajectory and can be used as an assumption to require it.
+    pub fn keep_trajectories(
+        &self,
+        solver: &impl ConstraintSink<'ctx>,
+        previous: &Schedule,
+        qubits: &[usize],
+    ) -> Vec<ast::Bool<'ctx>> {
+        let context = self.zero.get_ctx();
+        qubits
+            .iter()
+            .map(|&ii| {
+                let q = &self.qubits[ii];
+                let keep = ast::Bool::new_const(context, format!("keep_q{}", ii));
+                for (jj, stage) in previous.iter().enumerate().take(q.x.len()) {
+                    let Some(a) = stage.atoms.get(ii) else {
+                        break;
+                    };
+                    solver.assert(&keep.implies(&q.x[jj]._eq(&self.x_domain.constant(a.x))));
+                    solver.assert(&keep.implies(&q.y[jj]._eq(&self.y_domain.constant(a.y))));
+                    solver.assert(
+                        &keep.implies(&q.aod[jj]._eq(&ast::Bool::from_bool(context, a.in_aod()))),
+                    );
+                    if let Some((c, r)) = a.aod {
+                        solver.assert(&keep.implies(&q.c[jj]._eq(&self.c_domain.constant(c))));
+                        solver.assert(&keep.implies(&q.r[jj]._eq(&self.r_domain.constant(r))));
+                    }
+                }
+                solver.assert_soft(&keep, 1);
+                keep
+            })
+            .collect()
+    }
+
+    /// Prefer to execute the gates at the same stages as in a previous
+    /// schedule, matching repeated gates in order. Returns one literal per
+    /// gate found in the schedule, as for `keep_trajectories`.
+    pub fn keep_gate_times(
+        &self,
+        solver: &impl ConstraintSink<'ctx>,
+        previous: &Schedule,
+    ) -> Vec<ast::Bool<'ctx>> {
+        let context = self.zero.get_ctx();
+        let mut stages: HashMap<TwoQubitGate, VecDeque<usize>> = HashMap::new();
+        for stage in previous.iter() {
+            for g in &stage.gates {
+                stages.entry(*g).or_default().push_back(stage.index);
+            }
+        }
+
+        let mut literals = vec![];
+        for (ii, (g, t)) in self.circuit.iter().zip(&self.t).enumerate() {
+            let Some(stage) = stages.get_mut(g).and_then(|s| s.pop_front()) else {
+                continue;
+            };
+            let keep = ast::Bool::new_const(context, format!("keep_t{}", ii));
+            solver.assert(&keep.implies(&t._eq(&self.t_domain.constant(stage as u64))));
+            solver.assert_soft(&keep, 1);
+            literals.push(keep);
+        }
+        literals
+    }
+
     /// Two qubits must be at the same grid position when an entangling gate
     /// is run on them
-    pub fn constraint_entangling_gates(&self, solver: &Optimize) {
+    pub fn constraint_entangling_gates(&self, solver: &impl ConstraintSink<'ctx>) {
         let context = solver.get_context();
         for (g, t) in izip!(self.circuit.iter(), self.t.iter()) {
             let (q0, q1) = (&self.qubits[g.q_ctrl], &self.qubits[g.q_target]);
             for (v0, v1, stage) in izip!(q0.iter(), q1.iter(), &self.s_vals) {
-                let same_pos = ast::Bool::and(&context, &[&v0.x._eq(&v1.x), &v0.y._eq(&v1.y)]);
-                solver.assert(&t._eq(&stage).implies(&same_pos));
+                let same_pos = ast::Bool::and(context, &[&v0.x._eq(v1.x), &v0.y._eq(v1.y)]);
+                solver.assert(&t._eq(stage).implies(&same_pos));
             }
         }
     }
 
-    /// Two qubits may only be at the same grid position if they are both
-    /// used by a gate
-    pub fn constraint_interaction_exactness(&self, solver: &Optimize) {
-        // Maps a pair of qubits q0, q1 (with q0 < q1) to the indices of the
-        // gate(s) that act on q0 and q1
+    /// Maps a pair of qubits q0, q1 (with q0 < q1) to the indices of the
+    /// gate(s) that act on q0 and q1
+    fn interactions(&self) -> HashMap<(usize, usize), Vec<usize>> {
         let mut interactions: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
         for (ii, g) in self.circuit.iter().enumerate() {
             let (q0, q1) = (g.q_ctrl.min(g.q_target), g.q_target.max(g.q_ctrl));
             interactions.entry((q0, q1)).or_default().push(ii);
         }
+        interactions
+    }
+
+    /// True if any of the given gates is executed at the given stage
+    fn gate_at(&self, gate_indices: &[usize], stage: &Coord<'ctx>) -> ast::Bool<'ctx> {
+        let context = self.zero.get_ctx();
+        if gate_indices.is_empty() {
+            return ast::Bool::from_bool(context, false);
+        }
+        let or_args: Vec<ast::Bool> = gate_indices
+            .iter()
+            .map(|&gg| self.t[gg]._eq(stage))
+            .collect();
+        ast::Bool::or(context, &or_args.iter().collect::<Vec<&ast::Bool<'ctx>>>())
+    }
 
+    /// True if any of the given measurements or resets is executed at the
+    /// given stage
+    fn op_at(&self, op_indices: &[usize], stage: &Coord<'ctx>) -> ast::Bool<'ctx> {
+        let or_args: Vec<ast::Bool> = op_indices
+            .iter()
+            .map(|&ii| self.t_ops[ii]._eq(stage))
+            .collect();
+        ast::Bool::or(
+            self.zero.get_ctx(),
+            &or_args.iter().collect::<Vec<&ast::Bool<'ctx>>>(),
+        )
+    }
+
+    /// For each qubit and stage, whether the qubit is illuminated by the
+    /// Rydberg laser: at every stage with a gate for a global pulse, or only
+    /// when the qubit is part of a gate for locally addressed beams
+    fn exposed(&self) -> Vec<Vec<ast::Bool<'ctx>>> {
+        let mut gates_on: Vec<Vec<usize>> = vec![vec![]; self.qubits.len()];
+        for (ii, g) in self.circuit.iter().enumerate() {
+            gates_on[g.q_ctrl].push(ii);
+            gates_on[g.q_target].push(ii);
+        }
+        let all_gates: Vec<usize> = (0..self.t.len()).collect();
+
+        gates_on
+            .iter()
+            .map(|gate_indices| {
+                let gate_indices = match self.addressing {
+                    Addressing::Global => &all_gates,
+                    _ => gate_indices,
+                };
+                self.s_vals
+                    .iter()
+                    .map(|stage| self.gate_at(gate_indices, stage))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Two qubits may only be at the same grid position if they are both
+    /// used by a gate
+    pub fn constraint_interaction_exactness(&self, solver: &impl ConstraintSink<'ctx>) {
+        let interactions = self.interactions();
         let context = solver.get_context();
+        let exposed = self.exposed();
 
         for ((ii0, q0), (ii1, q1)) in self.qubits.iter().enumerate().tuple_combinations() {
-            if let Some(gate_indices) = interactions.get(&(ii0, ii1)) {
-                // This pair of qubits can interact, but only at stages
-                // where both are used in a gate
-                for (v0, v1, stage) in izip!(q0.iter(), q1.iter(), self.s_vals.iter()) {
+            let gate_indices = interactions.get(&(ii0, ii1)).cloned().unwrap_or_default();
+            if let Addressing::Global = self.addressing {
+                // Co-located qubits always interact, so this pair can only
+                // share a site at stages where both are used in a gate.
+                // The laser is not fired during return stages.
+                let n_gate_stages = self.s_vals.len() - self.n_return_stages;
+                for (v0, v1, stage) in
+                    izip!(q0.iter(), q1.iter(), self.s_vals.iter()).take(n_gate_stages)
+                {
                     let qubits_coincident =
-                        ast::Bool::and(context, &[&v0.x._eq(&v1.x), &v0.y._eq(&v1.y)]);
-                    let or_args: Vec<ast::Bool> = gate_indices
-                        .iter()
-                        .map(|&gg| self.t[gg]._eq(&stage))
-                        .collect();
-                    let gate_condition = ast::Bool::or(
-                        &context,
-                        or_args.iter().collect::<Vec<&ast::Bool>>().as_slice(),
-                    );
-                    solver.assert(&qubits_coincident.implies(&gate_condition));
+                        ast::Bool::and(context, &[&v0.x._eq(v1.x), &v0.y._eq(v1.y)]);
+                    solver.assert(&qubits_coincident.implies(&self.gate_at(&gate_indices, stage)));
                 }
             } else {
-                // This pair of qubits cannot interact
-                for (v0, v1) in izip!(q0.iter(), q1.iter()) {
-                    let qubits_not_coincident =
-                        ast::Bool::or(&context, &[&v0.x._eq(&v1.x).not(), &v0.y._eq(&v1.y).not()]);
-                    solver.assert(&qubits_not_coincident);
+                // Co-located qubits only interact if their site is
+                // illuminated, i.e. if either of them is part of a gate
+                for (v0, v1, stage, e0, e1) in izip!(
+                    q0.iter(),
+                    q1.iter(),
+                    self.s_vals.iter(),
+                    &exposed[ii0],
+                    &exposed[ii1]
+                ) {
+                    let illuminated = ast::Bool::or(context, &[e0, e1]);
+                    let qubits_coincident =
+                        ast::Bool::and(context, &[&v0.x._eq(v1.x), &v0.y._eq(v1.y), &illuminated]);
+                    solver.assert(&qubits_coincident.implies(&self.gate_at(&gate_indices, stage)));
                 }
             }
         }
     }
 
+    /// With locally addressed beams, gates can only be executed at sites
+    /// covered by a beam
+    fn constraint_addressable_gates(&self, solver: &impl ConstraintSink<'ctx>) {
+        if let Addressing::Global = self.addressing {
+            return;
+        }
+        let context = self.zero.get_ctx();
+        let addressable = |x: &Coord<'ctx>, y: &Coord<'ctx>| -> ast::Bool<'ctx> {
+            let or_args: Vec<ast::Bool> = match &self.addressing {
+                Addressing::Global => return ast::Bool::from_bool(context, true),
+                Addressing::Sites(sites) => sites
+                    .iter()
+                    .map(|&(sx, sy)| {
+                        let sx = self.x_domain.constant(sx);
+                        let sy = self.y_domain.constant(sy);
+                        ast::Bool::and(context, &[&x._eq(&sx), &y._eq(&sy)])
+                    })
+                    .collect(),
+                Addressing::Rows(rows) => rows
+                    .iter()
+                    .map(|&row| y._eq(&self.y_domain.constant(row)))
+                    .collect(),
+                Addressing::Cols(cols) => cols
+                    .iter()
+                    .map(|&col| x._eq(&self.x_domain.constant(col)))
+                    .collect(),
+            };
+            ast::Bool::or(context, &or_args.iter().collect::<Vec<&ast::Bool<'ctx>>>())
+        };
+
+        for (t, g) in self.t.iter().zip(self.circuit.iter()) {
+            let q = &self.qubits[g.q_ctrl];
+            for (x, y, stage) in izip!(&q.x, &q.y, &self.s_vals) {
+                solver.assert(&t._eq(stage).implies(&addressable(x, y)));
+            }
+        }
+    }
+
+    /// Atoms illuminated by the Rydberg laser that are not part of the same
+    /// gate must be at least the interaction radius apart (in x or y). A
+    /// radius of 1 is already covered by `constraint_interaction_exactness`.
+    fn constraint_interaction_radius(&self, solver: &impl ConstraintSink<'ctx>) {
+        if self.interaction_radius <= 1 {
+            return;
+        }
+        let interactions = self.interactions();
+        let context = self.zero.get_ctx();
+        let radius = self.interaction_radius;
+        let exposed = self.exposed();
+
+        for ((ii0, q0), (ii1, q1)) in self.qubits.iter().enumerate().tuple_combinations() {
+            let gate_indices = interactions.get(&(ii0, ii1)).cloned().unwrap_or_default();
+            for (v0, v1, stage, e0, e1) in izip!(
+                q0.iter(),
+                q1.iter(),
+                self.s_vals.iter(),
+                &exposed[ii0],
+                &exposed[ii1]
+            ) {
+                let far = ast::Bool::or(
+                    context,
+                    &[
+                        &v0.x.diff_ge(v1.x, radius),
+                        &v1.x.diff_ge(v0.x, radius),
+                        &v0.y.diff_ge(v1.y, radius),
+                        &v1.y.diff_ge(v0.y, radius),
+                    ],
+                );
+                let spectators = ast::Bool::and(
+                    context,
+                    &[e0, e1, &self.gate_at(&gate_indices, stage).not()],
+                );
+                solver.assert(&spectators.implies(&far));
+            }
+        }
+    }
+
+    /// Whether mirroring the grid along x (or y) maps the addressable sites
+    /// and the readout zone onto themselves, so that mirrored schedules are
+    /// equally valid
+    fn mirror_symmetric(&self, along_x: bool) -> bool {
+        let (cols, rows) = (self.x_domain.size(), self.y_domain.size());
+        let mirror = |(x, y): (u64, u64)| {
+            if along_x {
+                (cols - 1 - x, y)
+            } else {
+                (x, rows - 1 - y)
+            }
+        };
+        let is_closed = |sites: Vec<(u64, u64)>| {
+            let sites: HashSet<(u64, u64)> = sites
+                .into_iter()
+                .filter(|&(x, y)| x < cols && y < rows)
+                .collect();
+            sites.iter().all(|&site| sites.contains(&mirror(site)))
+        };
+        let in_rows = |ys: &[u64]| (0..cols).cartesian_product(ys.iter().copied()).collect();
+
+        let addressable = match &self.addressing {
+            Addressing::Global => true,
+            Addressing::Sites(sites) => is_closed(sites.clone()),
+            Addressing::Rows(ys) => is_closed(in_rows(ys)),
+            Addressing::Cols(xs) => {
+                is_closed(xs.iter().copied().cartesian_product(0..rows).collect())
+            }
+        };
+        let readout = self
+            .readout_zone
+            .as_ref()
+            .is_none_or(|ys| is_closed(in_rows(ys)));
+        addressable && readout && !matches!(self.final_layout, FinalLayout::Custom(_))
+    }
+
+    /// Optional constraints that rule out symmetric copies of solutions,
+    /// which mostly speeds up proving that a circuit does not fit:
+    /// * if the grid is mirror symmetric, the first qubit of the first gate
+    ///   starts in the lower half along each symmetric axis,
+    /// * qubits without gates or operations are interchangeable, so they
+    ///   start in lexicographic order of (x, y, trap),
+    /// * the AOD indices of atoms in SLM traps, which are otherwise free, are
+    ///   0 unless the atom was just dropped.
+    ///
+    /// Any solution can be mirrored, relabelled and reindexed to satisfy
+    /// them, so they do not change feasibility, nor the optimal cost. They
+    /// are not compatible with a warm start, which pins down the positions.
+    pub fn constraint_symmetry_breaking(&self, solver: &impl ConstraintSink<'ctx>) {
+        let context = solver.get_context();
+
+        if let Some(g) = self.circuit.iter().next() {
+            let anchor = &self.qubits[g.q_ctrl];
+            for (along_x, var, domain) in [
+                (true, &anchor.x[0], &self.x_domain),
+                (false, &anchor.y[0], &self.y_domain),
+            ] {
+                if self.mirror_symmetric(along_x) {
+                    solver.assert(&var.le(&domain.constant((domain.size() - 1) / 2)));
+                }
+            }
+        }
+
+        if !matches!(self.final_layout, FinalLayout::Custom(_)) {
+            let mut used = vec![false; self.qubits.len()];
+            for g in self.circuit.iter() {
+                used[g.q_ctrl] = true;
+                used[g.q_target] = true;
+            }
+            for (_, op) in self.circuit.ops() {
+                used[op.qubit()] = true;
+            }
+            let idle = self.qubits.iter().zip(&used).filter(|(_, &u)| !u);
+            for ((q0, _), (q1, _)) in idle.tuple_windows() {
+                let aod_le = q0.aod[0].implies(&q1.aod[0]);
+                let y_le = ast::Bool::or(
+                    context,
+                    &[
+                        &q0.y[0].lt(&q1.y[0]),
+                        &ast::Bool::and(context, &[&q0.y[0]._eq(&q1.y[0]), &aod_le]),
+                    ],
+                );
+                let xy_le = ast::Bool::or(
+                    context,
+                    &[
+                        &q0.x[0].lt(&q1.x[0]),
+                        &ast::Bool::and(context, &[&q0.x[0]._eq(&q1.x[0]), &y_le]),
+                    ],
+                );
+                solver.assert(&xy_le);
+            }
+        }
+
+        let zero = self.c_domain.constant(0);
+        for q in &self.qubits {
+            for (jj, v) in q.iter().enumerate() {
+                let settled = match jj {
+                    0 => v.aod.not(),
+                    _ => ast::Bool::and(context, &[&v.aod.not(), &q.aod[jj - 1].not()]),
+                };
+                let cr_zero = ast::Bool::and(context, &[&v.c._eq(&zero), &v.r._eq(&zero)]);
+                solver.assert(&settled.implies(&cr_zero));
+            }
+        }
+    }
+
     /// If two gates are run at the same time, they must have the same type
-    fn constraint_gate_type_timing(&self, solver: &Optimize) {
+    fn constraint_gate_type_timing(&self, solver: &impl ConstraintSink<'ctx>) {
         for ((ii0, g0), (ii1, g1)) in self.circuit.iter().enumerate().tuple_combinations() {
             if g0.gate_type != g1.gate_type {
                 solver.assert(&self.t[ii0]._eq(&self.t[ii1]).not());
@@ -394,28 +908,58 @@ impl<'ctx, 'circ> DPQAVars<'ctx, 'circ> {
         }
     }
 
-    /// Set all constraints
-    pub fn set_constraints(&self, solver: &Optimize) {
-        // Architecture constraints
-        self.constraint_grid_bounds(solver);
-        self.constraint_fixed_slm(solver);
-        self.constraint_aod_move_together(solver);
-        self.constraint_slm_order_from_aod(solver);
-        self.constraint_aod_order_from_slm(solver);
-        self.constraint_aod_crowding(solver);
-        self.constraint_site_crowding(solver);
-        self.constraint_no_swap(solver);
-
-        // Circuit-dependent constraints
-        self.constraint_t_bounds(solver);
-        self.constraint_entangling_gates(solver);
-        self.constraint_interaction_exactness(solver);
-        self.constraint_gate_type_timing(solver);
+    /// Set all constraints, reporting the number of assertions added by each
+    /// family of constraints
+    pub fn set_constraints<S: ConstraintSink<'ctx>>(
+        &self,
+        solver: &S,
+        report: &mut impl FnMut(&'static str, usize),
+    ) {
+        type AddConstraints<'a, 'b, S> = &'a dyn Fn(&CountingSink<'b, S>);
+        let families: [(&str, AddConstraints<S>); 18] = [
+            // Architecture constraints
+            ("grid_bounds", &|s| self.constraint_grid_bounds(s)),
+            ("fixed_slm", &|s| self.constraint_fixed_slm(s)),
+            ("aod_move_together", &|s| {
+                self.constraint_aod_move_together(s)
+            }),
+            ("slm_order_from_aod", &|s| {
+                self.constraint_slm_order_from_aod(s)
+            }),
+            ("aod_order_from_slm", &|s| {
+                self.constraint_aod_order_from_slm(s)
+            }),
+            ("aod_crowding", &|s| self.constraint_aod_crowding(s)),
+            ("site_crowding", &|s| self.constraint_site_crowding(s)),
+            ("no_swap", &|s| self.constraint_no_swap(s)),
+            // Circuit-dependent constraints
+            ("t_bounds", &|s| self.constraint_t_bounds(s)),
+            ("entangling_gates", &|s| self.constraint_entangling_gates(s)),
+            ("interaction_exactness", &|s| {
+                self.constraint_interaction_exactness(s)
+            }),
+            ("gate_type_timing", &|s| self.constraint_gate_type_timing(s)),
+            ("addressable_gates", &|s| {
+                self.constraint_addressable_gates(s)
+            }),
+            ("interaction_radius", &|s| {
+                self.constraint_interaction_radius(s)
+            }),
+            ("ops_timing", &|s| self.constraint_ops_timing(s)),
+            ("readout_zone", &|s| self.constraint_readout_zone(s)),
+            ("cyclic", &|s| self.constraint_cyclic(s)),
+            ("final_layout", &|s| self.constraint_final_layout(s)),
+        ];
+        for (family, add) in families {
+            let counted = CountingSink::new(solver);
+            add(&counted);
+            report(family, counted.n_assertions());
+        }
     }
 
     /// Minimize the number of moves between trap types
-    fn minimize_transfers(&self, solver: &Optimize) {
-        if self.t.len() == 1 {
+    fn minimize_transfers(&self, solver: &impl ConstraintSink<'ctx>) {
+        if self.s_vals.len() == 1 {
             // If there is only one stage, there are no transfers
             return;
         }
@@ -427,18 +971,18 @@ impl<'ctx, 'circ> DPQAVars<'ctx, 'circ> {
             .flat_map(|q| {
                 q.aod.windows(2).map(|step| {
                     let (curr, next) = (&step[0], &step[1]);
-                    curr._eq(&next).ite(&self.zero, &self.one)
+                    curr._eq(next).ite(&self.zero, &self.one)
                 })
             })
             .collect();
-        let refs: Vec<&ast::Int> = transferred.iter().map(|v| v).collect();
+        let refs: Vec<&ast::Int<'ctx>> = transferred.iter().collect();
 
         let n_transfers = ast::Int::add(context, refs.as_slice());
         solver.minimize(&n_transfers);
     }
 
     /// Keep atoms in the stationary traps if possible
-    fn prefer_slm(&self, solver: &Optimize) {
+    fn prefer_slm(&self, solver: &impl ConstraintSink<'ctx>) {
         let context = solver.get_context();
 
         let in_aod: Vec<ast::Int<'_>> = self
@@ -446,63 +990,145 @@ impl<'ctx, 'circ> DPQAVars<'ctx, 'circ> {
             .iter()
             .flat_map(|q| q.aod.iter().map(|trap| trap.ite(&self.one, &self.zero)))
             .collect();
-        let refs: Vec<&ast::Int> = in_aod.iter().map(|v| v).collect();
+        let refs: Vec<&ast::Int<'ctx>> = in_aod.iter().collect();
 
         let aod_total = ast::Int::add(context, refs.as_slice());
         solver.minimize(&aod_total);
     }
 
     /// Set optimization targets
-    pub fn set_optimization(&self, solver: &Optimize) {
+    pub fn set_optimization(&self, solver: &impl ConstraintSink<'ctx>) {
         self.minimize_transfers(solver);
         self.prefer_slm(solver);
     }
 
-    /// Get the qubit positions and gate execution times. Panics
-    /// if solver state != Sat.
-    pub fn eval(&self, solver: &Optimize) -> DPQAVarsValues {
-        let model = solver.get_model().unwrap();
+    /// Get the qubit positions and gate execution times from a model of the
+    /// solver. Returns an error if any variable has no value in it.
+    pub fn eval(&self, model: &Model<'ctx>) -> Result<DPQAVarsValues, DPQAError> {
+        let get_u64 = |var: &ast::Dynamic<'ctx>| -> Result<u64, DPQAError> {
+            model
+                .eval(var, true)
+                .and_then(|v| match v.as_int() {
+                    Some(v) => v.as_u64(),
+                    None => v.as_bv()?.as_u64(),
+                })
+                .ok_or_else(|| DPQAError::ModelExtraction(format!("no value for {}", var)))
+        };
+        let get_bool = |var: &ast::Bool<'ctx>| -> Result<bool, DPQAError> {
+            model
+                .eval(var, true)
+                .and_then(|v| v.as_bool())
+                .ok_or_else(|| DPQAError::ModelExtraction(format!("no value for {}", var)))
+        };
+        self.values(get_u64, get_bool)
+    }
+
+    /// Get the qubit positions and gate execution times from the model
+    /// found by an external solver (see `backend::parse_model`). As with
+    /// Z3's model completion, variables missing from the model are taken to
+    /// be 0 or false. Returns an error if the values read do not satisfy the
+    /// constraints, e.g. if the model is truncated or for another instance.
+    pub fn eval_external(
+        &self,
+        model: &HashMap<String, ModelValue>,
+    ) -> Result<DPQAVarsValues, DPQAError> {
+        // Values read, to check them against the constraints
+        let assigned: RefCell<Vec<ast::Bool<'ctx>>> = RefCell::new(vec![]);
+        let get_u64 = |var: &ast::Dynamic<'ctx>| -> Result<u64, DPQAError> {
+            let value = match model.get(&var.to_string()) {
+                None => 0,
+                Some(&ModelValue::Int(v)) if v >= 0 => v as u64,
+                Some(v) => {
+                    return Err(DPQAError::ModelExtraction(format!(
+                        "invalid value {:?} for {}",
+                        v, var
+                    )))
+                }
+            };
+            let constant = match var.as_bv() {
+                Some(bv) => {
+                    ast::Dynamic::from_ast(&ast::BV::from_u64(var.get_ctx(), value, bv.get_size()))
+                }
+                None => ast::Dynamic::from_ast(&ast::Int::from_u64(var.get_ctx(), value)),
+            };
+            assigned.borrow_mut().push(var._eq(&constant));
+            Ok(value)
+        };
+        let get_bool = |var: &ast::Bool<'ctx>| -> Result<bool, DPQAError> {
+            let value = match model.get(&var.to_string()) {
+                None => false,
+                Some(&ModelValue::Bool(v)) => v,
+                Some(v) => {
+                    return Err(DPQAError::ModelExtraction(format!(
+                        "invalid value {:?} for {}",
+                        v, var
+                    )))
+                }
+            };
+            assigned
+                .borrow_mut()
+                .push(var._eq(&ast::Bool::from_bool(var.get_ctx(), value)));
+            Ok(value)
+        };
+        let values = self.values(get_u64, get_bool)?;
 
-        let get_u64 = |var: &ast::Int| -> u64 { model.eval(var, true).unwrap().as_u64().unwrap() };
+        let solver = LexOptimize::new(self.zero.get_ctx());
+        self.set_constraints(&solver, &mut |_, _| {});
+        for eq in assigned.into_inner() {
+            solver.assert(&eq);
+        }
+        match solver.check(&[], &|_| {}) {
+            SatResult::Sat => Ok(values),
+            SatResult::Unsat => Err(DPQAError::ModelExtraction(String::from(
+                "model violates the constraints",
+            ))),
+            SatResult::Unknown => Err(DPQAError::SolverUnknown(
+                solver.get_reason_unknown().unwrap_or_default(),
+            )),
+        }
+    }
 
+    fn values(
+        &self,
+        get_u64: impl Fn(&ast::Dynamic<'ctx>) -> Result<u64, DPQAError>,
+        get_bool: impl Fn(&ast::Bool<'ctx>) -> Result<bool, DPQAError>,
+    ) -> Result<DPQAVarsValues, DPQAError> {
+        let get_coord = |var: &Coord<'ctx>| var.value(&get_u64, &get_bool);
         let xy_result = self
             .qubits
             .iter()
             .map(|q| {
                 izip!(&q.x, &q.y)
-                    .map(|(x, y)| (get_u64(x), get_u64(y)))
+                    .map(|(x, y)| Ok((get_coord(x)?, get_coord(y)?)))
                     .collect()
             })
-            .collect();
+            .collect::<Result<_, DPQAError>>()?;
 
         let cr_result = self
             .qubits
             .iter()
             .map(|q| {
                 izip!(&q.c, &q.r)
-                    .map(|(c, r)| (get_u64(c), get_u64(r)))
+                    .map(|(c, r)| Ok((get_coord(c)?, get_coord(r)?)))
                     .collect()
             })
-            .collect();
+            .collect::<Result<_, DPQAError>>()?;
 
         let aod_result = self
             .qubits
             .iter()
-            .map(|q| {
-                q.aod
-                    .iter()
-                    .map(|v| model.eval(v, true).unwrap().as_bool().unwrap())
-                    .collect()
-            })
-            .collect();
+            .map(|q| q.aod.iter().map(&get_bool).collect())
+            .collect::<Result<_, DPQAError>>()?;
 
-        let t_result = self.t.iter().map(|v| get_u64(v)).collect();
+        let t_result = self.t.iter().map(get_coord).collect::<Result<_, _>>()?;
+        let t_ops_result = self.t_ops.iter().map(get_coord).collect::<Result<_, _>>()?;
 
-        DPQAVarsValues {
+        Ok(DPQAVarsValues {
             xy: xy_result,
             cr: cr_result,
             aod: aod_result,
             t: t_result,
-        }
+            t_ops: t_ops_result,
+        })
     }
 }